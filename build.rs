@@ -9,6 +9,7 @@ use std::{
 fn main() -> Result<(), Box<dyn Error>> {
     make_table_b()?;
     make_table_d()?;
+    make_code_flag_table()?;
 
     Ok(())
 }
@@ -219,3 +220,105 @@ fn make_table_d() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+const CODE_FLAG_INPUT: &str = "Tables/BUFRCREX_CodeFlag_en.xml";
+// A partial copy of Common Code Table C-2 (002011 radiosonde type) and C-7 (002014 tracking
+// technique), which the element code tables only refer to. It only has the entries listed in it,
+// not the full WMO tables.
+const COMMON_CODE_INPUT: &str = "Tables/CommonCodeTables_C2_C7_partial_en.csv";
+const CODE_FLAG_OUTPUT: &str = "src/tables/code_flag.rs";
+
+fn make_code_flag_table() -> Result<(), Box<dyn Error>> {
+    let mut code_flag = HashMap::new();
+
+    let mut reader = Reader::from_reader(BufReader::new(File::open(CODE_FLAG_INPUT)?));
+    reader.config_mut().trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut txt = String::new();
+
+    let mut fxy = String::new();
+    let mut code_figure = String::new();
+    let mut entry_name = String::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Eof => break,
+
+            Event::Start(e) => match e.name().as_ref() {
+                b"BUFRCREX_CodeFlag_en" => {
+                    fxy.clear();
+                    code_figure.clear();
+                    entry_name.clear();
+                }
+                _ => {
+                    txt.clear();
+                }
+            },
+            Event::End(e) => match e.name().as_ref() {
+                b"BUFRCREX_CodeFlag_en" => {
+                    // Skip ranges of reserved values and the "All N" missing value entries.
+                    if let Ok(code) = code_figure.parse::<u64>()
+                        && !entry_name.is_empty()
+                    {
+                        code_flag.insert((fxy.clone(), code), entry_name.clone());
+                    }
+                }
+                b"FXY" => {
+                    fxy.push_str(&txt);
+                }
+                b"CodeFigure" => {
+                    code_figure.push_str(&txt);
+                }
+                b"EntryName_en" => {
+                    entry_name.push_str(&txt);
+                }
+                _ => {}
+            },
+            Event::Text(e) => txt.push_str(&e.decode().unwrap()),
+
+            // There are several other `Event`s we do not consider here
+            _ => {}
+        }
+        // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
+        buf.clear();
+    }
+
+    // FXY,CodeFigure,EntryName_en - the name is the rest of the line so it may have commas.
+    let common_codes = std::fs::read_to_string(COMMON_CODE_INPUT)?;
+    for line in common_codes.lines().skip(1).filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.splitn(3, ',').collect();
+        if fields.len() != 3 {
+            return Err(format!("Invalid line in {}: {}", COMMON_CODE_INPUT, line).into());
+        }
+        code_flag.insert((fields[0].to_owned(), fields[1].parse::<u64>()?), fields[2].trim().to_owned());
+    }
+
+    let mut w = BufWriter::new(
+        OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(CODE_FLAG_OUTPUT)?,
+    );
+
+    // Output the rust hashmap
+    writeln!(w, "use lazy_static::lazy_static;")?;
+    writeln!(w, "use std::collections::HashMap;")?;
+    writeln!(w)?;
+    writeln!(w, "lazy_static! {{")?;
+    writeln!(
+        w,
+        "    pub static ref CODE_FLAG: HashMap<(&'static str, u64), &'static str> = ["
+    )?;
+
+    for ((fxy, code), name) in code_flag {
+        writeln!(w, r##"(("{}", {}), r#"{}"#),"##, fxy, code, name)?;
+    }
+
+    // Close out the hash table
+    writeln!(w, "        ].into_iter().collect();")?;
+    writeln!(w, "}}")?;
+
+    Ok(())
+}
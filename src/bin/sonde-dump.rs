@@ -1,6 +1,6 @@
 use std::{env, error::Error, path::Path};
 
-use sonde_bufr::load_309052_sounding;
+use sonde_bufr::load_sounding;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().skip(1).collect();
@@ -9,7 +9,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         return Ok(());
     }
 
-    let snd = load_309052_sounding(&Path::new(&args[0]))?;
+    let snd = load_sounding(&Path::new(&args[0]))?;
 
     println!();
     println!("---------- Sounding ---------- ");
@@ -1,4 +1,4 @@
-use sonde_bufr::{read_bufr_message, scan_to_bufr_start};
+use sonde_bufr::BufrReader;
 use std::{env, error::Error};
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -9,23 +9,15 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     let f = std::fs::File::open(&args[0])?;
-    let mut f = std::io::BufReader::new(f);
+    let f = std::io::BufReader::new(f);
 
-    loop {
-        let header = match scan_to_bufr_start(&mut f) {
-            Ok(h) => h,
-            Err(e) => {
-                println!("End of file or error: {}", e);
-                break;
-            }
-        };
-
-        match String::from_utf8(header) {
-            Ok(h) => println!("Header:\n\t{}", h),
-            Err(_) => println!("Header content is not a UTF-8 string."),
+    for (header, bufr) in BufrReader::new(f) {
+        println!("Header:\n\t{}", header);
+        if let Some(seq) = header.sequence_number() {
+            println!("\tChannel sequence number: {}", seq);
         }
 
-        let bufr = read_bufr_message(&mut f)?;
+        let bufr = bufr?;
 
         println!("BUFR Summary:\n{}", &bufr);
     }
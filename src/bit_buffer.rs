@@ -1,4 +1,4 @@
-use std::{error::Error, io::Read};
+use std::error::Error;
 
 use crate::tables::table_b;
 
@@ -8,9 +8,9 @@ const BYTE_ARRAY_SIZE: usize = if table_b::MAX_BIT_WIDTH % 8 == 0 {
     table_b::MAX_BIT_WIDTH / 8 + 1
 };
 
-pub(crate) struct BitBuffer {
-    // The whole data set loaded into memory
-    buffer: Vec<u8>,
+pub(crate) struct BitBuffer<'a> {
+    // The whole data set, borrowed from the message
+    buffer: &'a [u8],
 
     // Position of next bit to read in current byte
     bit_position: usize,
@@ -22,18 +22,14 @@ pub(crate) struct BitBuffer {
     byte_buffer: [u8; BYTE_ARRAY_SIZE],
 }
 
-impl BitBuffer {
-    pub fn new(reader: &mut dyn Read, to_read: usize) -> Result<Self, Box<dyn Error>> {
-        let mut buffer = Vec::with_capacity(to_read);
-        let num_read = reader.take(to_read.try_into()?).read_to_end(&mut buffer)?;
-        assert_eq!(num_read, to_read);
-
-        Ok(BitBuffer {
+impl<'a> BitBuffer<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        BitBuffer {
             buffer,
             byte_position: 0,
             bit_position: 0,
             byte_buffer: [0; BYTE_ARRAY_SIZE],
-        })
+        }
     }
 
     fn num_bytes_to_hold_bits(n: usize) -> usize {
@@ -197,45 +193,4 @@ impl BitBuffer {
             Ok(None)
         }
     }
-
-    pub fn read_usize(&mut self, bits: usize) -> Result<Option<usize>, Box<dyn Error>> {
-        let val = self.read_u64(bits)?;
-        match val {
-            Some(val) => Ok(Some(usize::try_from(val)?)),
-            None => Ok(None),
-        }
-    }
-
-    pub fn read_i64(
-        &mut self,
-        bits: usize,
-        reference_val: i64,
-    ) -> Result<Option<i64>, Box<dyn Error>> {
-        let val = self.read_u64(bits)?;
-
-        match val {
-            Some(val) => Ok(Some(i64::try_from(val)? + reference_val)),
-            None => Ok(None),
-        }
-    }
-
-    pub fn read_f64(
-        &mut self,
-        bits: usize,
-        reference_val: i64,
-        scale: i32,
-    ) -> Result<Option<f64>, Box<dyn Error>> {
-        let val = self
-            .read_i64(bits, reference_val)?
-            .map(|v| v as f64)
-            .map(|v| {
-                if scale != 0 {
-                    v / f64::powi(10.0, scale)
-                } else {
-                    v
-                }
-            });
-
-        Ok(val)
-    }
 }
@@ -1,154 +1,877 @@
-use std::{error::Error, iter::zip, path::Path};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{Read, Seek},
+    iter::zip,
+    path::Path,
+};
 
-use chrono::NaiveDate;
-use optional::{Optioned, none};
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use optional::{Noned, Optioned, none};
 
-use crate::{scan_to_bufr_start, read_bufr_message,
-    types::{Structure, Replication, Group},
+use crate::{find_bufr_start, scan_to_bufr_start, read_bufr_message, read_bufr_message_from_slice,
+    types::{BufrMessage, Structure, Group},
 };
 
-use metfor::{Celsius, HectoPascal, Kelvin, Knots, Meters, MetersPSec, WindSpdDir};
+use metfor::{Celsius, HectoPascal, Kelvin, Knots, Meters, MetersPSec, PaPS, WindSpdDir};
 use sounding_analysis::{Sounding, StationInfo};
 
+mod metadata;
+pub use metadata::{CodeValue, RadiosondeMetadata};
+
+mod aircraft;
+pub use aircraft::{AircraftObservation, AircraftProfile, FlightPhase, aircraft_observations, aircraft_profiles};
+
+mod classify;
+pub use classify::{MessageKind, TempPart, classify_message};
+
+mod cyclone;
+pub use cyclone::{CycloneTrack, TrackPoint, cyclone_tracks};
+
+mod forecast;
+pub use forecast::{forecast_soundings, load_forecast_soundings};
+
+mod heights;
+
+mod mapping;
+pub use mapping::{ElementMapping, ProfileMapping};
+
+mod moisture;
+pub use moisture::MoistureSource;
+use moisture::Moisture;
+
+mod occultation;
+pub use occultation::{OccultationLevel, OccultationProfile, occultation_profiles};
+
+mod ocean;
+pub use ocean::{OceanLevel, OceanProfile, ocean_profiles};
+
+mod profiler;
+pub use profiler::{ProfilerGate, ProfilerReport, profiler_reports};
+
+mod qc;
+pub use qc::QcChange;
+
+mod report;
+pub use report::{LevelInfo, RadiosondeReport, WindShear};
+
+mod station;
+pub use station::WigosId;
+use station::StationIdentity;
+
+mod retrieval;
+pub use retrieval::retrieved_soundings;
+
+mod synop;
+pub use synop::{Precipitation, SurfaceObservation, surface_observations};
+
+mod significance;
+pub use significance::VerticalSignificance;
+
+/** The WMO upper air templates (Table D sequences) that can be turned into a `Sounding`. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RadiosondeTemplate {
+    /// 309050 - PILOT, PILOT SHIP and PILOT MOBIL with pressure as the vertical coordinate.
+    PilotPressure,
+    /// 309051 - PILOT, PILOT SHIP and PILOT MOBIL with height as the vertical coordinate.
+    PilotHeight,
+    /// 309052 - TEMP, TEMP SHIP and TEMP MOBIL.
+    Temp,
+    /// 309053 - TEMP DROP (dropsondes).
+    TempDrop,
+    /// 309054 - CLIMAT TEMP and CLIMAT TEMP SHIP monthly means.
+    ClimatTemp,
+    /// 309055 - High resolution radiosonde data with geopotential height as the vertical coordinate.
+    HighResolutionHeight,
+    /// 309056 - Radiosonde descent data.
+    Descent,
+    /// 309057 - TEMP with higher precision of pressure and geopotential height.
+    TempHighPrecision,
+}
+
+impl RadiosondeTemplate {
+    /** Look up a template by its Table D descriptor, e.g. "309052". */
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "309050" => Some(Self::PilotPressure),
+            "309051" => Some(Self::PilotHeight),
+            "309052" => Some(Self::Temp),
+            "309053" => Some(Self::TempDrop),
+            "309054" => Some(Self::ClimatTemp),
+            "309055" => Some(Self::HighResolutionHeight),
+            "309056" => Some(Self::Descent),
+            "309057" => Some(Self::TempHighPrecision),
+            _ => None,
+        }
+    }
+
+    /** The Table D descriptor of this template. */
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::PilotPressure => "309050",
+            Self::PilotHeight => "309051",
+            Self::Temp => "309052",
+            Self::TempDrop => "309053",
+            Self::ClimatTemp => "309054",
+            Self::HighResolutionHeight => "309055",
+            Self::Descent => "309056",
+            Self::TempHighPrecision => "309057",
+        }
+    }
+}
+
+/** Table D sequences that hold the data for a single level in the supported templates. */
+const LEVEL_SEQUENCES: [&str; 5] = ["303050", "303052", "303054", "303055", "303056"];
+
+/** Load the first sounding in a file that uses one of the supported `RadiosondeTemplate`s.
+ *
+ * The file name is used as the source description of the sounding.
+ */
+pub fn load_sounding(path: &Path) -> Result<Sounding, Box<dyn Error>> {
+    load_report(path).map(RadiosondeReport::into_sounding)
+}
+
+/** Load the first sounding from any seekable source, e.g. an in memory archive member.
+ *
+ * Any bytes before the start of the BUFR message (such as a WMO bulletin header) are skipped.
+ */
+pub fn load_sounding_from_reader<S>(
+    f: impl Read + Seek,
+    source_description: S,
+) -> Result<Sounding, Box<dyn Error>>
+where
+    Option<String>: From<S>,
+{
+    load_report_from_reader(f, source_description).map(RadiosondeReport::into_sounding)
+}
+
+/** Load the first sounding from a byte slice, e.g. a bulletin pulled off a message queue. The
+ * message is decoded in place without copying it.
+ */
+pub fn load_sounding_from_slice<S>(
+    bytes: &[u8],
+    source_description: S,
+) -> Result<Sounding, Box<dyn Error>>
+where
+    Option<String>: From<S>,
+{
+    load_report_from_slice(bytes, source_description).map(RadiosondeReport::into_sounding)
+}
+
+/** Same as `load_sounding`, kept from when 309052 was the only supported template. */
 pub fn load_309052_sounding(path: &Path) -> Result<Sounding, Box<dyn Error>> {
+    load_sounding(path)
+}
+
+/** Same as `load_sounding_from_reader`, kept from when 309052 was the only supported template. */
+pub fn load_309052_sounding_from_reader<S>(
+    f: impl Read + Seek,
+    source_description: S,
+) -> Result<Sounding, Box<dyn Error>>
+where
+    Option<String>: From<S>,
+{
+    load_sounding_from_reader(f, source_description)
+}
+
+/** Same as `load_sounding_from_slice`, kept from when 309052 was the only supported template. */
+pub fn load_309052_sounding_from_slice<S>(
+    bytes: &[u8],
+    source_description: S,
+) -> Result<Sounding, Box<dyn Error>>
+where
+    Option<String>: From<S>,
+{
+    load_sounding_from_slice(bytes, source_description)
+}
+
+/** Build a sounding from the first supported `RadiosondeTemplate` in an already decoded message. */
+pub fn sounding_from_message(bufr: &BufrMessage) -> Result<Sounding, Box<dyn Error>> {
+    report_from_message(bufr).map(RadiosondeReport::into_sounding)
+}
+
+/** Load the first report in a file that uses one of the supported `RadiosondeTemplate`s.
+ *
+ * The file name is used as the source description of the sounding.
+ */
+pub fn load_report(path: &Path) -> Result<RadiosondeReport, Box<dyn Error>> {
     let f = std::fs::File::open(path)?;
-    let mut f = std::io::BufReader::new(f);
+    let f = std::io::BufReader::new(f);
 
     let file_name = path
         .file_name()
         .map(|f| f.to_string_lossy().to_string())
         .unwrap_or_else(|| "Unknown file.".to_owned());
 
-    scan_to_bufr_start(&mut f)?; 
+    load_report_from_reader(f, file_name)
+}
+
+/** Load the first report from any seekable source, see `load_sounding_from_reader`. */
+pub fn load_report_from_reader<S>(
+    mut f: impl Read + Seek,
+    source_description: S,
+) -> Result<RadiosondeReport, Box<dyn Error>>
+where
+    Option<String>: From<S>,
+{
+    scan_to_bufr_start(&mut f)?;
     let bufr = read_bufr_message(&mut f)?;
 
-    let mut station = StationInfo::new();
-    let mut snd = Sounding::new();
+    let mut report = report_from_message(&bufr)?;
+    report.sounding = report.sounding.with_source_description(source_description);
+    Ok(report)
+}
 
-    let mut pres: Vec<Optioned<HectoPascal>> = vec![];
-    let mut temp: Vec<Optioned<Celsius>> = vec![];
-    let mut dewp: Vec<Optioned<Celsius>> = vec![];
-    let mut hgt: Vec<Optioned<Meters>> = vec![];
-    let mut dir: Vec<Optioned<f64>> = vec![];
-    let mut spd: Vec<Optioned<Knots>> = vec![];
+/** Load the first report from a byte slice, see `load_sounding_from_slice`. */
+pub fn load_report_from_slice<S>(
+    bytes: &[u8],
+    source_description: S,
+) -> Result<RadiosondeReport, Box<dyn Error>>
+where
+    Option<String>: From<S>,
+{
+    let start = find_bufr_start(bytes).ok_or(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "No bufr messages in slice.",
+    ))?;
+    let bufr = read_bufr_message_from_slice(&bytes[start..])?;
+
+    let mut report = report_from_message(&bufr)?;
+    report.sounding = report.sounding.with_source_description(source_description);
+    Ok(report)
+}
+
+/** Build a report from the first supported `RadiosondeTemplate` in an already decoded message.
+ *
+ * Levels with a higher pressure than the surface, like the standard levels below ground in a TEMP
+ * from a high station, are dropped.
+ */
+pub fn report_from_message(bufr: &BufrMessage) -> Result<RadiosondeReport, Box<dyn Error>> {
+    let is_template =
+        |s: &&Structure| matches!(s, Structure::Group(grp) if RadiosondeTemplate::from_code(grp.code()).is_some());
 
     for structure in bufr.get_elements() {
+        if let Structure::Group(grp) = structure
+            && let Some(template) = RadiosondeTemplate::from_code(grp.code())
+        {
+            // Some centers put the WIGOS identifier before the template and more metadata after it,
+            // e.g. the serial number.
+            let mut identity = StationIdentity::default();
+            identity.extract(bufr.get_elements().iter().filter(|s| !is_template(s)));
 
-        // Find the sounding format I'm looking for.
-        if let Structure::Group(grp) = structure && grp.code() == "309052" {
-            
-            // Iterate the members of the Group
-            for structure in grp.items() {
+            let mut report = report_from_template_group(grp, template, identity);
+            report.metadata.extract(bufr.get_elements().iter().filter(|s| !is_template(s)));
 
-                // A nested group with location & station info
-                if let Structure::Group(grp) = structure {
-                    match grp.code() {
+            return Ok(report);
+        }
+    }
 
-                        "301111" => station = extract_station_id_from_group(grp, station),
-                        "301113" => snd = extract_time_info_from_group(grp, snd),
-                        "301114" => station = extract_station_location_from_group(grp, station),
-                        _ => {}
-                    }
-                } else if let Structure::Replication(rep) = structure {
-                    if rep.len() > pres.capacity() {
-                        pres.reserve(rep.len());
-                        temp.reserve(rep.len());
-                        dewp.reserve(rep.len());
-                        hgt.reserve(rep.len());
-                        dir.reserve(rep.len());
-                        spd.reserve(rep.len());
-                    }
+    Err(Box::new(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "No supported radiosonde template in message.",
+    )))
+}
+
+/** Merge soundings that are parts of the same report into one sounding per station and time.
+ *
+ * Data converted from traditional alphanumeric TEMP reports arrives as a separate message for each
+ * of parts A, B, C, and D. Soundings with the same station number, station id, and valid time are
+ * combined into a single sounding with the levels sorted by decreasing pressure. When several parts
+ * report the same pressure level the values are merged, with the first non-missing value for each
+ * variable winning. Levels without a pressure are dropped since they cannot be placed in the
+ * merged profile. The order of the returned soundings follows the first appearance of each
+ * station and time in the input.
+ */
+pub fn merge_temp_parts(soundings: impl IntoIterator<Item = Sounding>) -> Vec<Sounding> {
+    let mut reports: Vec<Vec<Sounding>> = vec![];
+    let mut index: HashMap<ReportKey, usize> = HashMap::new();
+
+    for snd in soundings {
+        let key = (
+            snd.station_info().station_num().into_option(),
+            snd.station_info().station_id().map(String::from),
+            snd.valid_time(),
+        );
+
+        match index.get(&key) {
+            Some(&i) => reports[i].push(snd),
+            None => {
+                index.insert(key, reports.len());
+                reports.push(vec![snd]);
+            }
+        }
+    }
+
+    reports.into_iter().map(merge_report_parts).collect()
+}
+
+/** Station number, station id, and valid time, which identify the parts of the same report. */
+type ReportKey = (Option<i32>, Option<String>, Option<NaiveDateTime>);
+
+/** A single level while merging the parts of a report. */
+struct MergeLevel {
+    pres: HectoPascal,
+    temp: Optioned<Celsius>,
+    dewp: Optioned<Celsius>,
+    hgt: Optioned<Meters>,
+    wind: Optioned<WindSpdDir<Knots>>,
+}
+
+fn merge_report_parts(parts: Vec<Sounding>) -> Sounding {
+    if parts.len() == 1 {
+        return parts.into_iter().next().unwrap();
+    }
+
+    fn level_value<T: Noned + Copy>(profile: &[Optioned<T>], i: usize) -> Optioned<T> {
+        profile.get(i).copied().unwrap_or_else(none)
+    }
+
+    fn first_some<T: Noned + Copy>(parts: &[Sounding], get: impl Fn(&Sounding) -> Optioned<T>) -> Optioned<T> {
+        parts.iter().map(get).find(|v| v.is_some()).unwrap_or_else(none)
+    }
+
+    let mut levels: Vec<MergeLevel> = vec![];
+    for part in &parts {
+        // The first value in each profile is the surface value, not a reported level.
+        for (i, p) in part.pressure_profile().iter().enumerate().skip(1) {
+            if let Some(pres) = p.into_option() {
+                levels.push(MergeLevel {
+                    pres,
+                    temp: level_value(part.temperature_profile(), i),
+                    dewp: level_value(part.dew_point_profile(), i),
+                    hgt: level_value(part.height_profile(), i),
+                    wind: level_value(part.wind_profile(), i),
+                });
+            }
+        }
+    }
+
+    // Stable sort, so the first part to report a level comes first among duplicates.
+    levels.sort_by(|a, b| b.pres.0.total_cmp(&a.pres.0));
+
+    // Pressure is reported in units of 10 Pa, so compare levels at 0.1 hPa resolution.
+    let level_key = |lvl: &MergeLevel| (lvl.pres.0 * 10.0).round() as i64;
+
+    let mut merged: Vec<MergeLevel> = Vec::with_capacity(levels.len());
+    for lvl in levels {
+        match merged.last_mut() {
+            Some(prev) if level_key(prev) == level_key(&lvl) => {
+                if prev.temp.is_none() {
+                    prev.temp = lvl.temp;
+                }
+                if prev.dewp.is_none() {
+                    prev.dewp = lvl.dewp;
+                }
+                if prev.hgt.is_none() {
+                    prev.hgt = lvl.hgt;
+                }
+                if prev.wind.is_none() {
+                    prev.wind = lvl.wind;
+                }
+            }
+            _ => merged.push(lvl),
+        }
+    }
+
+    let mut sources: Vec<&str> = vec![];
+    for src in parts.iter().filter_map(|p| p.source_description()) {
+        if !sources.contains(&src) {
+            sources.push(src);
+        }
+    }
+    let source = if sources.is_empty() { None } else { Some(sources.join(", ")) };
+
+    let first = &parts[0];
+    let mut snd = Sounding::new()
+        .with_source_description(source)
+        .with_station_info(first.station_info().clone())
+        .with_valid_time(first.valid_time())
+        .with_lead_time(first.lead_time())
+        .with_mslp(first_some(&parts, |p| p.mslp()))
+        .with_station_pressure(first_some(&parts, |p| p.station_pressure()))
+        .with_sfc_temperature(first_some(&parts, |p| p.sfc_temperature()))
+        .with_sfc_dew_point(first_some(&parts, |p| p.sfc_dew_point()))
+        .with_sfc_wind(first_some(&parts, |p| p.sfc_wind()))
+        .with_precipitation(first_some(&parts, |p| p.precipitation()))
+        .with_low_cloud(first_some(&parts, |p| p.low_cloud()))
+        .with_mid_cloud(first_some(&parts, |p| p.mid_cloud()))
+        .with_high_cloud(first_some(&parts, |p| p.high_cloud()));
+
+    snd = snd.with_pressure_profile(merged.iter().map(|lvl| lvl.pres.into()).collect());
+    snd = snd.with_temperature_profile(merged.iter().map(|lvl| lvl.temp).collect());
+    snd = snd.with_dew_point_profile(merged.iter().map(|lvl| lvl.dewp).collect());
+    snd = snd.with_height_profile(merged.iter().map(|lvl| lvl.hgt).collect());
+    snd = snd.with_wind_profile(merged.iter().map(|lvl| lvl.wind).collect());
+
+    snd
+}
+
+fn report_from_template_group(
+    grp: &Group,
+    template: RadiosondeTemplate,
+    mut identity: StationIdentity,
+) -> RadiosondeReport {
+    debug_assert_eq!(grp.code(), template.code());
+
+    let mut station = StationInfo::new();
+    let mut snd = Sounding::new();
+    let mut time = LaunchTime::default();
+    let mut profiles = Profiles::default();
+    let mut surface = Surface::default();
+    let mut metadata = RadiosondeMetadata::default();
+    metadata.extract(grp.items());
+    identity.extract(grp.items());
+    let mut wind_shear: Vec<WindShear> = vec![];
+    let mapping = ProfileMapping::radiosonde();
+
+    // Some templates (309054, 309056) put the location directly in the template.
+    station = extract_station_location(grp.items(), station);
+
+    // Iterate the members of the Group
+    for structure in grp.items() {
+        match structure {
+            // A nested group with location, time & station info
+            Structure::Group(grp) => match grp.code() {
+                "301113" | "301011" | "301012" | "301013" => extract_time_info(grp.items(), &mut time),
+                "301114" => station = extract_station_location(grp.items(), station),
+                "302032" => surface.extract_temperature_and_humidity(grp),
+                "302049" => surface.extract_cloud_info(grp),
+                _ => {}
+            },
+
+            // Surface data reported directly in the template (309055)
+            Structure::Element(el) => match el.code() {
+                "010004" => surface.pres = el.get_f64_val().map(|x| x / 100.0).map(HectoPascal).into(),
+                "011001" => surface.dir = el.get_f64_val().into(),
+                "011002" => surface.spd = el.get_f64_val().map(MetersPSec).map(Knots::from).into(),
+                _ => {}
+            },
+
+            Structure::Replication(rep) => {
+                profiles.reserve(rep.len());
+
+                for block in rep.blocks() {
+                    let level = match block {
+                        [Structure::Group(grp)] if LEVEL_SEQUENCES.contains(&grp.code()) => grp.items(),
+                        [Structure::Group(grp)] if grp.code() == "303051" || grp.code() == "303053" => {
+                            wind_shear.extend(extract_wind_shear(grp));
+                            continue;
+                        }
+                        // CLIMAT TEMP replicates the level elements directly.
+                        _ if template == RadiosondeTemplate::ClimatTemp => block,
+                        _ => continue,
+                    };
 
-                    extract_data_rows(rep, &mut pres, &mut temp, &mut dewp, &mut hgt, &mut dir, &mut spd);
+                    profiles.extract_data_row(&mapping, level);
                 }
             }
         }
     }
 
-    let wnd: Vec<Optioned<WindSpdDir<Knots>>> = zip(dir, spd)
-        .map(|(d, s): (Optioned<f64>, Optioned<Knots>)| {
-            s.and_then(|ss| d.map(|dd|  WindSpdDir { speed: ss, direction: dd }).into())
-        })
-        .collect();
+    station = identity.apply(station);
+
+    let launch_time = time.to_datetime();
+    let launch_site = station.location();
+
+    // The sounding keeps the surface values first in every profile, so move the level flagged as
+    // the surface out of the profiles and use it to fill in any surface values not reported
+    // separately.
+    let sfc_level = profiles.take_surface_level(&mut surface);
+    if let Some(sfc_pres) = surface.pres.into_option() {
+        profiles.remove_below_surface(sfc_pres);
+    }
+
+    let wnd = profiles.wind();
+    let mut levels = profiles.levels;
+    if !levels.is_empty() {
+        let launch = sfc_level.unwrap_or_else(|| LevelInfo {
+            time_offset: Some(TimeDelta::zero()),
+            displacement: Some((0.0, 0.0)),
+            ..LevelInfo::default()
+        });
+        levels.insert(0, launch);
+        levels[0].dew_point_source = surface.dewp_source;
+    }
+    for lvl in levels.iter_mut() {
+        lvl.time = launch_time.zip(lvl.time_offset).map(|(t, dt)| t + dt);
+        lvl.location = launch_site
+            .zip(lvl.displacement)
+            .map(|((lat, lon), (dlat, dlon))| (lat + dlat, lon + dlon));
+    }
+
+    if let Some(vt) = launch_time {
+        snd = snd.with_valid_time(vt);
+    }
 
     snd = snd.with_station_info(station);
-    snd = snd.with_source_description(file_name);
-    snd = snd.with_pressure_profile(pres);
-    snd = snd.with_temperature_profile(temp);
-    snd = snd.with_dew_point_profile(dewp);
-    snd = snd.with_height_profile(hgt);
+    snd = snd.with_station_pressure(surface.pres);
+    snd = snd.with_sfc_temperature(surface.temp);
+    snd = snd.with_sfc_dew_point(surface.dewp);
+    snd = snd.with_sfc_wind(surface.wind());
+    snd = snd.with_low_cloud(surface.clouds.low);
+    snd = snd.with_mid_cloud(surface.clouds.mid);
+    snd = snd.with_high_cloud(surface.clouds.high);
+    snd = snd.with_pressure_profile(profiles.pres);
+    snd = snd.with_temperature_profile(profiles.temp);
+    snd = snd.with_dew_point_profile(profiles.dewp);
+    snd = snd.with_height_profile(profiles.hgt);
     snd = snd.with_wind_profile(wnd);
 
-    Ok(snd)
+    match_max_wind_levels(&mut wind_shear, &snd, &levels);
+
+    RadiosondeReport {
+        sounding: snd,
+        levels,
+        metadata,
+        wind_shear,
+        wigos_id: identity.wigos_id(),
+    }
 }
 
+/** Extract the wind shear from a 303051 or 303053 group, returns `None` if no shear was reported. */
+fn extract_wind_shear(grp: &Group) -> Option<WindShear> {
+    let mut shear = WindShear::default();
 
-fn extract_data_rows(
-    rep: &Replication,
-    pres: &mut Vec<Optioned<HectoPascal>>,
-    temp: &mut Vec<Optioned<Celsius>>,
-    dewp: &mut Vec<Optioned<Celsius>>,
-    hgt: &mut Vec<Optioned<Meters>>,
-    dir: &mut Vec<Optioned<f64>>,
-    spd: &mut Vec<Optioned<Knots>>,
-) {
-    for structure in rep.items() {
-        if let Structure::Group(grp) = structure
-            && grp.code() == "303054"
-        {
-            for structure in grp.items() {
-                if let Structure::Element(el) = structure {
-                    match el.code() {
-                        "007004" => {
-                            pres.push(el.get_f64_val().map(|x| x / 100.0).map(HectoPascal).into())
-                        }
-                        "010009" => hgt.push(el.get_f64_val().map(Meters).into()),
-                        "011001" => dir.push(el.get_f64_val().into()),
-                        "011002" => {
-                            spd.push(el.get_f64_val().map(MetersPSec).map(Knots::from).into())
-                        }
-                        "012101" => {
-                            temp.push(el.get_f64_val().map(Kelvin).map(Celsius::from).into())
-                        }
-                        "012103" => {
-                            dewp.push(el.get_f64_val().map(Kelvin).map(Celsius::from).into())
-                        }
-                        _ => {}
-                    }
+    for structure in grp.items() {
+        if let Structure::Element(el) = structure {
+            match el.code() {
+                "004086" => shear.time_offset = el.get_i32_val().map(|dt| TimeDelta::seconds(i64::from(dt))),
+                "005015" => shear.displacement = el.get_f64_val().map(|dlat| (dlat, 0.0)),
+                "006015" => {
+                    shear.displacement = shear.displacement.zip(el.get_f64_val()).map(|((dlat, _), dlon)| (dlat, dlon))
                 }
+                "007004" => shear.pressure = el.get_f64_val().map(|x| x / 100.0).map(HectoPascal),
+                "007009" => shear.height = el.get_f64_val().map(Meters),
+                "008042" => shear.significance = el.get_code_val().map(VerticalSignificance::from_008042),
+                "011061" => shear.below = el.get_f64_val().map(MetersPSec),
+                "011062" => shear.above = el.get_f64_val().map(MetersPSec),
+                _ => {}
             }
         }
     }
+
+    if shear.below.is_none() && shear.above.is_none() {
+        None
+    } else {
+        Some(shear)
+    }
 }
 
-fn extract_station_id_from_group(grp: &Group, mut station: StationInfo) -> StationInfo {
-    debug_assert_eq!(grp.code(), "301111");
+/** Find the maximum wind level at the same pressure (to 0.1 hPa) or height as each wind shear. */
+fn match_max_wind_levels(wind_shear: &mut [WindShear], snd: &Sounding, levels: &[LevelInfo]) {
+    for shear in wind_shear.iter_mut() {
+        shear.max_wind_level = levels
+            .iter()
+            .enumerate()
+            .filter(|(_, lvl)| lvl.significance.is_some_and(|sig| sig.is_max_wind()))
+            .map(|(i, _)| i)
+            .find(|&i| {
+                let p = snd.pressure_profile().get(i).and_then(|p| p.into_option());
+                let z = snd.height_profile().get(i).and_then(|z| z.into_option());
+
+                match (shear.pressure, p, shear.height, z) {
+                    (Some(sp), Some(p), _, _) => ((sp.0 - p.0) * 10.0).round() == 0.0,
+                    (_, _, Some(sz), Some(z)) => (sz.0 - z.0).abs() < 0.5,
+                    _ => false,
+                }
+            });
+    }
+}
 
-    for structure in grp.items() {
-        if let Structure::Element(el) = structure && el.code() == "001011" {
-            station = station.with_station_id(el.get_str_val().map(String::from));
-            break;
+/** Surface values, reported separately in some templates or taken from the surface level. */
+#[derive(Default)]
+struct Surface {
+    pres: Optioned<HectoPascal>,
+    temp: Optioned<Celsius>,
+    dewp: Optioned<Celsius>,
+    dewp_source: Option<MoistureSource>,
+    dir: Optioned<f64>,
+    spd: Optioned<Knots>,
+    clouds: CloudCover,
+}
+
+impl Surface {
+    fn wind(&self) -> Optioned<WindSpdDir<Knots>> {
+        self.spd
+            .and_then(|ss| self.dir.map(|dd| WindSpdDir { speed: ss, direction: dd }).into())
+    }
+
+    fn extract_temperature_and_humidity(&mut self, grp: &Group) {
+        debug_assert_eq!(grp.code(), "302032");
+
+        let mut moisture = Moisture::default();
+
+        for structure in grp.items() {
+            match structure {
+                Structure::Element(el) if el.code() == "012101" => {
+                    self.temp = el.get_f64_val().map(Kelvin).map(Celsius::from).into()
+                }
+                Structure::Element(el) if el.code() == "012103" => {
+                    moisture.dew_point = el.get_f64_val().map(Kelvin).map(Celsius::from)
+                }
+                Structure::Element(el) if el.code() == "013003" => moisture.relative_humidity = el.get_f64_val(),
+                _ => {}
+            }
+        }
+
+        if let Some((dewp, source)) = moisture.dew_point(self.temp, self.pres) {
+            self.dewp = dewp.into();
+            self.dewp_source = Some(source);
         }
     }
 
-    station
+    fn extract_cloud_info(&mut self, grp: &Group) {
+        debug_assert_eq!(grp.code(), "302049");
+        self.clouds = CloudCover::extract(grp.items());
+    }
+}
+
+/** Low, middle, and high cloud fractions. */
+#[derive(Clone, Copy, Debug, Default)]
+struct CloudCover {
+    low: Optioned<f64>,
+    mid: Optioned<f64>,
+    high: Optioned<f64>,
+}
+
+impl CloudCover {
+    /** From the general cloud information in 302049 or 302004. The cloud amount (Nh) is for the
+     * low clouds, or middle clouds if there are no low clouds. The first vertical significance
+     * (008002) says which.
+     */
+    fn extract(items: &[Structure]) -> Self {
+        let mut cover = CloudCover::default();
+        let mut vertical_significance: Option<u64> = None;
+        let mut amount: Option<u64> = None;
+
+        for structure in items {
+            match structure {
+                Structure::Element(el) if el.code() == "008002" && vertical_significance.is_none() => {
+                    vertical_significance = el.get_code_val()
+                }
+                Structure::Element(el) if el.code() == "020011" => amount = el.get_code_val(),
+                _ => {}
+            }
+        }
+
+        let fraction: Optioned<f64> = match amount {
+            Some(oktas @ 0..=8) => oktas as f64 / 8.0,
+            // Few (1-2 oktas), scattered (3-4 oktas), broken (5-7 oktas)
+            Some(13) => 1.5 / 8.0,
+            Some(11) => 3.5 / 8.0,
+            Some(12) => 6.0 / 8.0,
+            _ => return cover,
+        }
+        .into();
+
+        match vertical_significance {
+            Some(8) => cover.mid = fraction,
+            Some(9) => cover.high = fraction,
+            _ => {
+                cover.low = fraction;
+                // No low clouds and no middle clouds either.
+                if amount == Some(0) {
+                    cover.mid = fraction;
+                }
+            }
+        }
+
+        cover
+    }
 }
 
-fn extract_station_location_from_group(grp: &Group, mut station: StationInfo) -> StationInfo {
-    debug_assert_eq!(grp.code(), "301114");
+/** The profiles being built up level by level, kept in parallel vectors. */
+#[derive(Default)]
+struct Profiles {
+    pres: Vec<Optioned<HectoPascal>>,
+    temp: Vec<Optioned<Celsius>>,
+    dewp: Vec<Optioned<Celsius>>,
+    hgt: Vec<Optioned<Meters>>,
+    dir: Vec<Optioned<f64>>,
+    spd: Vec<Optioned<Knots>>,
+    omega: Vec<Optioned<PaPS>>,
+    cloud: Vec<Optioned<f64>>,
+    levels: Vec<LevelInfo>,
+}
+
+impl Profiles {
+    fn reserve(&mut self, additional: usize) {
+        self.pres.reserve(additional);
+        self.temp.reserve(additional);
+        self.dewp.reserve(additional);
+        self.hgt.reserve(additional);
+        self.dir.reserve(additional);
+        self.spd.reserve(additional);
+        self.omega.reserve(additional);
+        self.cloud.reserve(additional);
+        self.levels.reserve(additional);
+    }
+
+    /** Remove the first level flagged as the surface and use it for any surface values that are
+     * still missing.
+     */
+    fn take_surface_level(&mut self, surface: &mut Surface) -> Option<LevelInfo> {
+        let i = self
+            .levels
+            .iter()
+            .position(|lvl| lvl.significance.is_some_and(|sig| sig.is_surface()))?;
+
+        let pres = self.pres.remove(i);
+        let temp = self.temp.remove(i);
+        let dewp = self.dewp.remove(i);
+        let dir = self.dir.remove(i);
+        let spd = self.spd.remove(i);
+        self.hgt.remove(i);
+        self.omega.remove(i);
+        self.cloud.remove(i);
+
+        if surface.pres.is_none() {
+            surface.pres = pres;
+        }
+        if surface.temp.is_none() {
+            surface.temp = temp;
+        }
+        if surface.dewp.is_none() {
+            surface.dewp = dewp;
+            surface.dewp_source = self.levels[i].dew_point_source;
+        }
+        if surface.dir.is_none() || surface.spd.is_none() {
+            surface.dir = dir;
+            surface.spd = spd;
+        }
+
+        Some(self.levels.remove(i))
+    }
+
+    /** Remove levels with a higher pressure than the surface. TEMP reports still include the
+     * standard levels that are below ground, e.g. 1000 hPa at a high station, with only an
+     * extrapolated height.
+     */
+    fn remove_below_surface(&mut self, surface_pressure: HectoPascal) {
+        let keep: Vec<usize> = (0..self.pres.len())
+            .filter(|&i| self.pres[i].into_option().is_none_or(|p| p <= surface_pressure))
+            .collect();
+
+        self.select(&keep);
+    }
+
+    /** Sort the levels by decreasing pressure. The sort is stable, and levels without a pressure
+     * go to the top.
+     */
+    fn sort_by_pressure(&mut self) {
+        let mut order: Vec<usize> = (0..self.pres.len()).collect();
+        order.sort_by(|&a, &b| {
+            let pa = self.pres[a].into_option().map_or(f64::NEG_INFINITY, |p| p.0);
+            let pb = self.pres[b].into_option().map_or(f64::NEG_INFINITY, |p| p.0);
+            pb.total_cmp(&pa)
+        });
+
+        self.select(&order);
+    }
+
+    /** Keep only the levels at these indexes, in this order. */
+    fn select(&mut self, order: &[usize]) {
+        fn select<T>(values: &mut Vec<T>, order: &[usize]) {
+            let mut old: Vec<Option<T>> = std::mem::take(values).into_iter().map(Some).collect();
+            *values = order.iter().filter_map(|&i| old[i].take()).collect();
+        }
+
+        select(&mut self.pres, order);
+        select(&mut self.temp, order);
+        select(&mut self.dewp, order);
+        select(&mut self.hgt, order);
+        select(&mut self.dir, order);
+        select(&mut self.spd, order);
+        select(&mut self.omega, order);
+        select(&mut self.cloud, order);
+        select(&mut self.levels, order);
+    }
+
+    /** Combine the wind direction and speed profiles. */
+    fn wind(&self) -> Vec<Optioned<WindSpdDir<Knots>>> {
+        zip(&self.dir, &self.spd)
+            .map(|(d, s)| s.and_then(|ss| d.map(|dd| WindSpdDir { speed: ss, direction: dd }).into()))
+            .collect()
+    }
 
+    /** Extract the values for a single level, variables missing from the level are pushed as
+     * missing so the profiles stay the same length.
+     *
+     * The variables come from the mapping. A dew point is derived from the humidity elements when
+     * the mapping doesn't find one, and the time, displacement, and significance of the level are
+     * read from their usual elements.
+     */
+    fn extract_data_row(&mut self, mapping: &ProfileMapping, level: &[Structure]) {
+        let values = mapping.level_values(level);
+        let p: Optioned<HectoPascal> = values.pres.map(HectoPascal).into();
+        let t: Optioned<Celsius> = values.temp.map(Celsius).into();
+        let mut moisture = Moisture {
+            dew_point: values.dewp.map(Celsius),
+            ..Moisture::default()
+        };
+
+        let mut dt: Option<i32> = None;
+        let mut dlat: Option<f64> = None;
+        let mut dlon: Option<f64> = None;
+        let mut sig: Option<VerticalSignificance> = None;
+
+        for structure in level {
+            if let Structure::Element(el) = structure {
+                match el.code() {
+                    "004086" => dt = el.get_i32_val(),
+                    "005015" => dlat = el.get_f64_val(),
+                    "006015" => dlon = el.get_f64_val(),
+                    "008001" => sig = el.get_code_val().map(VerticalSignificance::from_008001),
+                    "008042" => sig = el.get_code_val().map(VerticalSignificance::from_008042),
+                    "013003" | "013009" => moisture.relative_humidity = el.get_f64_val(),
+                    "013002" => moisture.mixing_ratio = el.get_f64_val(),
+                    "013001" => moisture.specific_humidity = el.get_f64_val(),
+                    _ => {}
+                }
+            }
+        }
+
+        let (td, dew_point_source) = match moisture.dew_point(t, p) {
+            Some((td, source)) => (td.into(), Some(source)),
+            None => (none(), None),
+        };
+
+        self.pres.push(p);
+        self.temp.push(t);
+        self.dewp.push(td);
+        self.hgt.push(values.hgt.map(Meters).into());
+        self.dir.push(values.dir.into());
+        self.spd.push(values.spd.map(MetersPSec).map(Knots::from).into());
+        self.omega.push(values.omega.map(PaPS).into());
+        self.cloud.push(values.cloud.into());
+        self.levels.push(LevelInfo {
+            time_offset: dt.map(|dt| TimeDelta::seconds(i64::from(dt))),
+            displacement: dlat.zip(dlon),
+            significance: sig,
+            dew_point_source,
+            ..LevelInfo::default()
+        });
+    }
+}
+
+/** Look for the launch site coordinates in a 301114 group or directly in a template. */
+fn extract_station_location(items: &[Structure], mut station: StationInfo) -> StationInfo {
     let mut lat: Option<f64> = None;
     let mut lon: Option<f64> = None;
     let mut elev: Optioned<Meters> = none();
 
-    for structure in grp.items() {
+    for structure in items {
         match structure {
-            Structure::Element(el) if el.code() == "007030" || el.code() == "007007" => {
-                if elev.is_none() && el.get_f64_val().is_some() {
-                    elev = el.get_f64_val().map(Meters).into();
-                }
+            Structure::Element(el)
+                if (el.code() == "007030" || el.code() == "007007") && elev.is_none() =>
+            {
+                elev = el.get_f64_val().map(Meters).into();
             }
 
+            // Some templates (e.g. wind profilers) have the location outside of a 301021 sequence.
+            Structure::Element(el) if el.code() == "005001" && lat.is_none() => lat = el.get_f64_val(),
+            Structure::Element(el) if el.code() == "006001" && lon.is_none() => lon = el.get_f64_val(),
+
             Structure::Group(grp) if grp.code() == "301021" => {
                 for structure in grp.items() {
                     match structure {
@@ -163,46 +886,98 @@ fn extract_station_location_from_group(grp: &Group, mut station: StationInfo) ->
         }
     }
 
-    station = station.with_lat_lon(lat.zip(lon));
-    station = station.with_elevation(elev);
+    if let Some(location) = lat.zip(lon) {
+        station = station.with_lat_lon(location);
+    }
+    if elev.is_some() {
+        station = station.with_elevation(elev);
+    }
     station
 }
 
-fn extract_time_info_from_group(grp: &Group, mut snd: Sounding) -> Sounding {
-    debug_assert_eq!(grp.code(), "301113");
+/** The parts of the launch time, which may be spread across several groups. */
+#[derive(Debug, Default)]
+struct LaunchTime {
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
 
-    let mut year: i32 = 0;
-    let mut month: u32 = 0;
-    let mut day: u32 = 0;
-    let mut hour: u32 = 0;
-    let mut minute: u32 = 0;
-    let mut second: u32 = 0;
+impl LaunchTime {
+    fn to_datetime(&self) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(self.year, self.month, self.day)
+            .and_then(|d| d.and_hms_opt(self.hour, self.minute, self.second))
+    }
+}
 
-    for structure in grp.items() {
-        if let Structure::Group(grp) = structure && grp.code() == "301011" {
-            for structure in grp.items() {
-                match structure {
-                    Structure::Element(el) if el.code() == "004001" => year = el.get_i32_val().unwrap_or(0),
-                    Structure::Element(el) if el.code() == "004002" => month = el.get_u32_val().unwrap_or(0),
-                    Structure::Element(el) if el.code() == "004003" => day = el.get_u32_val().unwrap_or(0),
-                    _ => {},
-                }
+/** Collect the date and time elements from 301113 or its 301011/301012/301013 members. */
+fn extract_time_info(items: &[Structure], time: &mut LaunchTime) {
+    for structure in items {
+        match structure {
+            Structure::Group(grp) if ["301011", "301012", "301013"].contains(&grp.code()) => {
+                extract_time_info(grp.items(), time)
             }
-        } else if let Structure::Group(grp) = structure && grp.code() == "301013" {
-            for structure in grp.items() {
-                match structure {
-                    Structure::Element(el) if el.code() == "004004" => hour = el.get_u32_val().unwrap_or(0),
-                    Structure::Element(el) if el.code() == "004005" => minute = el.get_u32_val().unwrap_or(0),
-                    Structure::Element(el) if el.code() == "004006" => second = el.get_u32_val().unwrap_or(0),
-                    _ => {},
-                }
+            Structure::Element(el) if el.code() == "004001" => time.year = el.get_i32_val().unwrap_or(0),
+            Structure::Element(el) if el.code() == "004002" => time.month = el.get_u32_val().unwrap_or(0),
+            Structure::Element(el) if el.code() == "004003" => time.day = el.get_u32_val().unwrap_or(0),
+            Structure::Element(el) if el.code() == "004004" => time.hour = el.get_u32_val().unwrap_or(0),
+            Structure::Element(el) if el.code() == "004005" => time.minute = el.get_u32_val().unwrap_or(0),
+            // Seconds may be fractional, e.g. in satellite data.
+            Structure::Element(el) if el.code() == "004006" => {
+                time.second = el.get_u32_val().or(el.get_f64_val().map(|s| s as u32)).unwrap_or(0)
             }
+            _ => {}
         }
     }
+}
 
-    if let Some(vt) = NaiveDate::from_ymd_opt(year, month, day).and_then(|d| d.and_hms_opt(hour, minute, second)) {
-        snd = snd.with_valid_time(vt);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(station: i32, source: &str, levels: &[(Option<f64>, Option<f64>, Option<f64>)]) -> Sounding {
+        let valid_time = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let celsius = |t: Option<f64>| Optioned::<Celsius>::from(t.map(Celsius));
+
+        Sounding::new()
+            .with_source_description(source.to_owned())
+            .with_station_info(StationInfo::new().with_station(station))
+            .with_valid_time(valid_time)
+            .with_pressure_profile(levels.iter().map(|lvl| lvl.0.map(HectoPascal).into()).collect())
+            .with_temperature_profile(levels.iter().map(|lvl| celsius(lvl.1)).collect())
+            .with_dew_point_profile(levels.iter().map(|lvl| celsius(lvl.2)).collect())
     }
 
-    snd
+    #[test]
+    fn test_merge_temp_parts() {
+        let part_a = part(72776, "Part A", &[(Some(850.0), Some(10.0), None), (Some(700.0), Some(0.0), None)])
+            .with_station_pressure(HectoPascal(900.0));
+        let other = part(72777, "Part A", &[(Some(850.0), Some(12.0), None)]);
+        let part_b = part(
+            72776,
+            "Part B",
+            &[(Some(800.0), Some(5.0), None), (Some(700.0), Some(1.0), Some(-5.0)), (None, Some(-10.0), None)],
+        );
+
+        let merged = merge_temp_parts([part_a, other, part_b]);
+        assert_eq!(merged.len(), 2);
+
+        let snd = &merged[0];
+        assert_eq!(snd.station_info().station_num().into_option(), Some(72776));
+        assert_eq!(snd.source_description(), Some("Part A, Part B"));
+        assert_eq!(snd.station_pressure().into_option(), Some(HectoPascal(900.0)));
+
+        // The surface, then the levels of both parts sorted with the duplicate 700 hPa merged.
+        let pres: Vec<Option<f64>> = snd.pressure_profile().iter().map(|p| p.into_option().map(|p| p.0)).collect();
+        assert_eq!(pres, vec![Some(900.0), Some(850.0), Some(800.0), Some(700.0)]);
+        assert_eq!(snd.temperature_profile()[3].into_option(), Some(Celsius(0.0)));
+        assert_eq!(snd.dew_point_profile()[3].into_option(), Some(Celsius(-5.0)));
+
+        // A single part is returned as it is.
+        assert_eq!(merged[1].station_info().station_num().into_option(), Some(72777));
+        assert_eq!(merged[1].pressure_profile().len(), 2);
+    }
 }
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use metfor::{Celsius, HectoPascal, Kelvin, Knots, Meters, MetersPSec, WindSpdDir};
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::{LevelInfo, Moisture};
+use crate::types::{BufrMessage, Structure};
+
+/** Table D sequences with a single aircraft observation. */
+pub(super) const AIRCRAFT_SEQUENCES: [&str; 4] = ["311001", "311005", "311010", "311011"];
+
+/** Observations of the same aircraft and phase further apart than this start a new profile. */
+const MAX_PROFILE_GAP_MINUTES: i64 = 20;
+
+/** Phase of flight from 008004 or the detailed phase of flight in 008009. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FlightPhase {
+    Level,
+    Ascending,
+    Descending,
+    Unsteady,
+}
+
+impl FlightPhase {
+    fn from_008004(code: u64) -> Option<Self> {
+        match code {
+            2 => Some(FlightPhase::Unsteady),
+            3 | 4 => Some(FlightPhase::Level),
+            5 => Some(FlightPhase::Ascending),
+            6 => Some(FlightPhase::Descending),
+            _ => None,
+        }
+    }
+
+    fn from_008009(code: u64) -> Option<Self> {
+        match code {
+            0 | 1 | 3 | 4 => Some(FlightPhase::Level),
+            2 => Some(FlightPhase::Unsteady),
+            5 | 7..=10 => Some(FlightPhase::Ascending),
+            6 | 11..=14 => Some(FlightPhase::Descending),
+            _ => None,
+        }
+    }
+}
+
+/** A single aircraft observation (311001, 311005, 311010, or 311011). */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AircraftObservation {
+    pub(super) registration: Option<String>,
+    pub(super) flight_number: Option<String>,
+    pub(super) time: Option<NaiveDateTime>,
+    pub(super) location: Option<(f64, f64)>,
+    pub(super) phase: Option<FlightPhase>,
+    pub(super) pressure: Option<HectoPascal>,
+    pub(super) pressure_altitude: Option<Meters>,
+    pub(super) gnss_altitude: Option<Meters>,
+    pub(super) temperature: Option<Celsius>,
+    pub(super) dew_point: Option<Celsius>,
+    pub(super) wind: Option<WindSpdDir<Knots>>,
+}
+
+impl AircraftObservation {
+    /** Aircraft registration number or other identification, the tail number (001008). */
+    pub fn registration(&self) -> Option<&str> {
+        self.registration.as_deref()
+    }
+
+    /** Flight number (001006). */
+    pub fn flight_number(&self) -> Option<&str> {
+        self.flight_number.as_deref()
+    }
+
+    /** Time of the observation. */
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        self.time
+    }
+
+    /** Latitude and longitude. */
+    pub fn location(&self) -> Option<(f64, f64)> {
+        self.location
+    }
+
+    /** Phase of flight (008004 or 008009). */
+    pub fn phase(&self) -> Option<FlightPhase> {
+        self.phase
+    }
+
+    /** Pressure, reported (007004) or computed from the pressure altitude with the ICAO standard
+     * atmosphere.
+     */
+    pub fn pressure(&self) -> Option<HectoPascal> {
+        self.pressure
+    }
+
+    /** Flight level or pressure altitude (007010 or 007002). */
+    pub fn pressure_altitude(&self) -> Option<Meters> {
+        self.pressure_altitude
+    }
+
+    /** Altitude from a global navigation satellite system (010053). */
+    pub fn gnss_altitude(&self) -> Option<Meters> {
+        self.gnss_altitude
+    }
+
+    /** Air temperature. */
+    pub fn temperature(&self) -> Option<Celsius> {
+        self.temperature
+    }
+
+    /** Dew point, reported or derived from the mixing ratio or relative humidity. */
+    pub fn dew_point(&self) -> Option<Celsius> {
+        self.dew_point
+    }
+
+    /** Wind speed and direction. */
+    pub fn wind(&self) -> Option<WindSpdDir<Knots>> {
+        self.wind
+    }
+
+    /** The tail number, or the flight number if there isn't one. */
+    fn aircraft(&self) -> Option<&str> {
+        self.registration().or(self.flight_number())
+    }
+}
+
+/** An ascent or descent of a single aircraft as a sounding. */
+#[derive(Clone, Debug)]
+pub struct AircraftProfile {
+    pub(super) aircraft: String,
+    pub(super) phase: FlightPhase,
+    pub(super) sounding: Sounding,
+    pub(super) levels: Vec<LevelInfo>,
+}
+
+impl AircraftProfile {
+    /** The tail number, or the flight number for aircraft that don't report a tail number. */
+    pub fn aircraft(&self) -> &str {
+        &self.aircraft
+    }
+
+    /** Ascending or descending. */
+    pub fn phase(&self) -> FlightPhase {
+        self.phase
+    }
+
+    /** Get the sounding. */
+    pub fn sounding(&self) -> &Sounding {
+        &self.sounding
+    }
+
+    /** Throw away everything but the sounding. */
+    pub fn into_sounding(self) -> Sounding {
+        self.sounding
+    }
+
+    /** Get the per-level time and position, these line up with the sounding profiles.
+     *
+     * Offsets and displacements are relative to the lowest observation, which is the station
+     * location and valid time of the sounding. The first entry is for the empty surface level.
+     */
+    pub fn levels(&self) -> &[LevelInfo] {
+        &self.levels
+    }
+}
+
+/** Get the aircraft observations from every subset of a message. */
+pub fn aircraft_observations(bufr: &BufrMessage) -> Vec<AircraftObservation> {
+    let mut observations = vec![];
+    for subset in bufr.subsets() {
+        find_observations(subset, &mut observations);
+    }
+    observations
+}
+
+fn find_observations(items: &[Structure], observations: &mut Vec<AircraftObservation>) {
+    for structure in items {
+        match structure {
+            Structure::Group(grp) if AIRCRAFT_SEQUENCES.contains(&grp.code()) => {
+                let mut obs = ObservationBuilder::default();
+                obs.extract(grp.items());
+                observations.push(obs.build());
+            }
+            Structure::Group(grp) => find_observations(grp.items(), observations),
+            Structure::Replication(rep) => find_observations(rep.items(), observations),
+            Structure::Element(_) => {}
+        }
+    }
+}
+
+/** Group observations by aircraft and phase of flight into ascent and descent profiles.
+ *
+ * Observations from any number of messages can be combined. Observations without an aircraft
+ * identifier, a time, or a pressure can't be placed in a profile and are skipped, as are level
+ * and unsteady flight. A new profile is started when there is a gap of more than 20 minutes
+ * between observations. Each profile has at least two levels, sorted by decreasing pressure.
+ */
+pub fn aircraft_profiles(observations: impl IntoIterator<Item = AircraftObservation>) -> Vec<AircraftProfile> {
+    let mut flights: Vec<Vec<AircraftObservation>> = vec![];
+    let mut index: HashMap<(String, FlightPhase), usize> = HashMap::new();
+
+    for obs in observations {
+        let phase = match obs.phase {
+            Some(phase @ (FlightPhase::Ascending | FlightPhase::Descending)) => phase,
+            _ => continue,
+        };
+        let aircraft = match obs.aircraft() {
+            Some(aircraft) if obs.time.is_some() && obs.pressure.is_some() => aircraft.to_owned(),
+            _ => continue,
+        };
+
+        match index.get(&(aircraft.clone(), phase)) {
+            Some(&i) => flights[i].push(obs),
+            None => {
+                index.insert((aircraft, phase), flights.len());
+                flights.push(vec![obs]);
+            }
+        }
+    }
+
+    let mut profiles = vec![];
+    for mut flight in flights {
+        flight.sort_by_key(|obs| obs.time);
+
+        let mut start = 0;
+        for i in 1..=flight.len() {
+            let gap = flight
+                .get(i)
+                .zip(flight.get(i - 1))
+                .and_then(|(obs, prev)| Some(obs.time? - prev.time?));
+            if gap.is_some_and(|gap| gap <= TimeDelta::minutes(MAX_PROFILE_GAP_MINUTES)) {
+                continue;
+            }
+
+            if i - start >= 2 {
+                profiles.push(build_profile(&flight[start..i]));
+            }
+            start = i;
+        }
+    }
+
+    profiles
+}
+
+fn build_profile(observations: &[AircraftObservation]) -> AircraftProfile {
+    let mut obs: Vec<&AircraftObservation> = observations.iter().collect();
+    obs.sort_by(|a, b| b.pressure.unwrap().0.total_cmp(&a.pressure.unwrap().0));
+
+    let lowest = obs[0];
+    let valid_time = lowest.time;
+
+    let mut station = StationInfo::new().with_station_id(lowest.aircraft().map(String::from));
+    if let Some(location) = lowest.location {
+        station = station.with_lat_lon(location);
+    }
+
+    let mut levels = vec![LevelInfo::default()];
+    levels.extend(obs.iter().map(|o| LevelInfo {
+        time_offset: o.time.zip(valid_time).map(|(t, vt)| t - vt),
+        time: o.time,
+        displacement: o
+            .location
+            .zip(lowest.location)
+            .map(|((lat, lon), (lat0, lon0))| (lat - lat0, lon - lon0)),
+        location: o.location,
+        ..LevelInfo::default()
+    }));
+
+    let mut snd = Sounding::new().with_station_info(station);
+    if let Some(vt) = valid_time {
+        snd = snd.with_valid_time(vt);
+    }
+
+    // Pressure altitude isn't a geopotential height, so only the GNSS altitude goes in the height
+    // profile.
+    snd = snd
+        .with_pressure_profile(obs.iter().map(|o| o.pressure.into()).collect())
+        .with_temperature_profile(obs.iter().map(|o| o.temperature.into()).collect())
+        .with_dew_point_profile(obs.iter().map(|o| o.dew_point.into()).collect())
+        .with_height_profile(obs.iter().map(|o| o.gnss_altitude.into()).collect())
+        .with_wind_profile(obs.iter().map(|o| o.wind.into()).collect());
+
+    AircraftProfile {
+        aircraft: lowest.aircraft().unwrap_or_default().to_owned(),
+        phase: lowest.phase.unwrap_or(FlightPhase::Ascending),
+        sounding: snd,
+        levels,
+    }
+}
+
+/** Pressure from a pressure altitude with the ICAO standard atmosphere. */
+fn standard_atmosphere_pressure(altitude: Meters) -> HectoPascal {
+    let z = altitude.0;
+    if z <= 11_000.0 {
+        HectoPascal(1013.25 * (1.0 - 2.255_77e-5 * z).powf(5.255_88))
+    } else {
+        HectoPascal(226.32 * (-(z - 11_000.0) / 6_341.62).exp())
+    }
+}
+
+/** Collects the first value of each element in an observation. */
+#[derive(Default)]
+struct ObservationBuilder {
+    obs: AircraftObservation,
+    hms: (Option<u32>, Option<u32>, Option<u32>),
+    lat: Option<f64>,
+    lon: Option<f64>,
+    dir: Option<f64>,
+    spd: Option<Knots>,
+    moisture: Moisture,
+    year: Option<i32>,
+    month: Option<u32>,
+    day: Option<u32>,
+}
+
+impl ObservationBuilder {
+    fn extract(&mut self, items: &[Structure]) {
+        for structure in items {
+            let el = match structure {
+                Structure::Element(el) => el,
+                Structure::Group(grp) => {
+                    self.extract(grp.items());
+                    continue;
+                }
+                // Single elements reported only when present, like the dew point in 311010. Larger
+                // replications are other events along the flight, like turbulence, with their own
+                // times and positions.
+                Structure::Replication(rep) => {
+                    if let Some([Structure::Element(_)]) = rep.blocks().next() {
+                        self.extract(rep.items());
+                    }
+                    continue;
+                }
+            };
+
+            let text = || el.get_str_val().map(str::trim).filter(|s| !s.is_empty()).map(String::from);
+            let obs = &mut self.obs;
+
+            match el.code() {
+                "001008" if obs.registration.is_none() => obs.registration = text(),
+                "001006" if obs.flight_number.is_none() => obs.flight_number = text(),
+                "004001" if self.year.is_none() => self.year = el.get_i32_val(),
+                "004002" if self.month.is_none() => self.month = el.get_u32_val(),
+                "004003" if self.day.is_none() => self.day = el.get_u32_val(),
+                "004004" if self.hms.0.is_none() => self.hms.0 = el.get_u32_val(),
+                "004005" if self.hms.1.is_none() => self.hms.1 = el.get_u32_val(),
+                "004006" if self.hms.2.is_none() => self.hms.2 = el.get_u32_val(),
+                "005001" | "005002" if self.lat.is_none() => self.lat = el.get_f64_val(),
+                "006001" | "006002" if self.lon.is_none() => self.lon = el.get_f64_val(),
+                "008004" if obs.phase.is_none() => obs.phase = el.get_code_val().and_then(FlightPhase::from_008004),
+                // The detailed phase of flight is more specific, so it wins over 008004.
+                "008009" => {
+                    if let Some(phase) = el.get_code_val().and_then(FlightPhase::from_008009) {
+                        obs.phase = Some(phase);
+                    }
+                }
+                "007004" if obs.pressure.is_none() => obs.pressure = el.get_f64_val().map(|p| HectoPascal(p / 100.0)),
+                "007002" | "007010" if obs.pressure_altitude.is_none() => {
+                    obs.pressure_altitude = el.get_f64_val().map(Meters)
+                }
+                "010053" if obs.gnss_altitude.is_none() => obs.gnss_altitude = el.get_f64_val().map(Meters),
+                "012001" | "012101" if obs.temperature.is_none() => {
+                    obs.temperature = el.get_f64_val().map(Kelvin).map(Celsius::from)
+                }
+                "012103" if self.moisture.dew_point.is_none() => {
+                    self.moisture.dew_point = el.get_f64_val().map(Kelvin).map(Celsius::from)
+                }
+                "013002" if self.moisture.mixing_ratio.is_none() => self.moisture.mixing_ratio = el.get_f64_val(),
+                "013003" if self.moisture.relative_humidity.is_none() => {
+                    self.moisture.relative_humidity = el.get_f64_val()
+                }
+                "011001" if self.dir.is_none() => self.dir = el.get_f64_val(),
+                "011002" if self.spd.is_none() => self.spd = el.get_f64_val().map(MetersPSec).map(Knots::from),
+                _ => {}
+            }
+        }
+    }
+
+    fn build(self) -> AircraftObservation {
+        let (h, min, s) = self.hms;
+
+        let mut obs = self.obs;
+        obs.time = self
+            .year
+            .zip(self.month)
+            .zip(self.day)
+            .and_then(|((y, m), d)| NaiveDate::from_ymd_opt(y, m, d))
+            .and_then(|date| date.and_hms_opt(h?, min?, s.unwrap_or(0)));
+        obs.location = self.lat.zip(self.lon);
+        obs.wind = self
+            .dir
+            .zip(self.spd)
+            .map(|(direction, speed)| WindSpdDir { speed, direction });
+
+        if obs.pressure.is_none() {
+            obs.pressure = obs.pressure_altitude.map(standard_atmosphere_pressure);
+        }
+
+        obs.dew_point = self
+            .moisture
+            .dew_point(obs.temperature.into(), obs.pressure.into())
+            .map(|(td, _)| td);
+
+        obs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(aircraft: &str, phase: FlightPhase, minutes: i64, pressure: Option<f64>) -> AircraftObservation {
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+
+        AircraftObservation {
+            registration: Some(aircraft.to_owned()),
+            time: Some(start + TimeDelta::minutes(minutes)),
+            location: Some((40.0, -100.0 + minutes as f64 / 100.0)),
+            phase: Some(phase),
+            pressure: pressure.map(HectoPascal),
+            temperature: Some(Celsius(0.0)),
+            ..AircraftObservation::default()
+        }
+    }
+
+    fn pressures(profile: &AircraftProfile) -> Vec<Option<f64>> {
+        profile.sounding().pressure_profile().iter().map(|p| p.into_option().map(|p| p.0)).collect()
+    }
+
+    #[test]
+    fn test_aircraft_profiles() {
+        use FlightPhase::*;
+
+        let mut by_flight_number = obs("", Ascending, 0, Some(1000.0));
+        by_flight_number.registration = None;
+        by_flight_number.flight_number = Some("AB123".to_owned());
+
+        let observations = vec![
+            obs("N1", Ascending, 10, Some(800.0)),
+            obs("N1", Ascending, 0, Some(1000.0)),
+            obs("N1", Descending, 65, Some(700.0)),
+            obs("N1", Ascending, 5, Some(900.0)),
+            obs("N1", Level, 30, Some(650.0)),
+            // More than 20 minutes after the last ascending observation.
+            obs("N1", Ascending, 40, Some(700.0)),
+            obs("N1", Ascending, 45, Some(600.0)),
+            obs("N1", Descending, 60, Some(600.0)),
+            obs("N1", Descending, 62, None),
+            obs("N2", Ascending, 0, Some(1000.0)),
+            by_flight_number.clone(),
+            AircraftObservation {
+                time: Some(by_flight_number.time.unwrap() + TimeDelta::minutes(1)),
+                pressure: Some(HectoPascal(950.0)),
+                ..by_flight_number
+            },
+        ];
+
+        let profiles = aircraft_profiles(observations);
+        assert_eq!(profiles.len(), 4);
+
+        // The surface is empty, so the levels start at index 1.
+        assert_eq!(profiles[0].aircraft(), "N1");
+        assert_eq!(profiles[0].phase(), Ascending);
+        assert_eq!(pressures(&profiles[0])[1..], [Some(1000.0), Some(900.0), Some(800.0)]);
+        assert_eq!(profiles[0].levels().len(), 4);
+        assert_eq!(profiles[0].levels()[3].time_offset(), Some(TimeDelta::minutes(10)));
+        assert_eq!(profiles[0].sounding().station_info().location(), Some((40.0, -100.0)));
+
+        assert_eq!(profiles[1].phase(), Ascending);
+        assert_eq!(pressures(&profiles[1])[1..], [Some(700.0), Some(600.0)]);
+
+        // A descent is sorted by pressure too, so the time offsets are negative going up.
+        assert_eq!(profiles[2].phase(), Descending);
+        assert_eq!(pressures(&profiles[2])[1..], [Some(700.0), Some(600.0)]);
+        assert_eq!(profiles[2].levels()[2].time_offset(), Some(TimeDelta::minutes(-5)));
+
+        assert_eq!(profiles[3].aircraft(), "AB123");
+        assert_eq!(pressures(&profiles[3])[1..], [Some(1000.0), Some(950.0)]);
+    }
+
+    #[test]
+    fn test_standard_atmosphere_pressure() {
+        assert!((standard_atmosphere_pressure(Meters(0.0)).0 - 1013.25).abs() < 1e-9);
+        assert!((standard_atmosphere_pressure(Meters(11_000.0)).0 - 226.32).abs() < 0.01);
+        assert!((standard_atmosphere_pressure(Meters(16_000.0)).0 - 102.87).abs() < 0.1);
+    }
+}
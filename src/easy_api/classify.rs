@@ -0,0 +1,311 @@
+use std::fmt::Display;
+
+use super::{
+    RadiosondeTemplate, VerticalSignificance, aircraft::AIRCRAFT_SEQUENCES, cyclone::CYCLONE_TEMPLATE,
+    occultation::OCCULTATION_TEMPLATE, ocean::OCEAN_TEMPLATES, profiler::PROFILER_TEMPLATES,
+    synop::SYNOP_TEMPLATES,
+};
+use crate::types::{BufrMessage, Structure};
+
+/** Data category (BUFR Table A) for vertical soundings from satellites. */
+const SATELLITE_SOUNDINGS_CATEGORY: u8 = 3;
+
+/** Data category (BUFR Table A) for BUFR tables. */
+const TABLES_CATEGORY: u8 = 11;
+
+/** The 100 hPa level, in Pa, that separates parts A and B from parts C and D. */
+const PART_C_D_BELOW: f64 = 10_000.0;
+
+/** The part of a report converted from a traditional alphanumeric TEMP or PILOT, each of which
+ * arrives as a separate message.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TempPart {
+    /** Standard levels up to 100 hPa. */
+    A,
+    /** Significant levels up to 100 hPa. */
+    B,
+    /** Standard levels above 100 hPa. */
+    C,
+    /** Significant levels above 100 hPa. */
+    D,
+}
+
+/** What kind of data a message holds, see `classify_message`. */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    /** An upper air sounding, with the part if it only has the levels of one part of a
+     * traditional alphanumeric report.
+     */
+    Radiosonde {
+        template: RadiosondeTemplate,
+        part: Option<TempPart>,
+    },
+    /** Aircraft observations (AMDAR, ACARS). */
+    Amdar,
+    /** Surface observations from a land station. */
+    Synop,
+    /** Wind profiler or RASS reports. */
+    WindProfiler,
+    /** Subsurface ocean profiles from floats and XBTs. */
+    OceanProfile,
+    /** GNSS radio occultation profiles. */
+    RadioOccultation,
+    /** Profiles retrieved from satellite radiances, which don't have a WMO template. */
+    SatelliteRetrieval,
+    /** Tropical cyclone track forecasts. */
+    CycloneTrack,
+    /** Model forecast profiles that use local descriptors, like NCEP's point forecasts. They need
+     * the tables from the `Tables` messages ahead of them to decode.
+     */
+    ForecastSounding,
+    /** BUFR tables, like the DX tables NCEP sends ahead of its forecast soundings. */
+    Tables,
+    /** The first Table D sequence in section 3, which doesn't have an extractor. */
+    Template(String),
+    /** No Table D sequences in section 3. */
+    Unknown,
+}
+
+impl MessageKind {
+    /** Whether there is a function in this crate that extracts the data in the message.
+     *
+     * `Radiosonde` => `report_from_message`, `Amdar` => `aircraft_observations`, `Synop` =>
+     * `surface_observations`, `WindProfiler` => `profiler_reports`, `OceanProfile` =>
+     * `ocean_profiles`, `RadioOccultation` => `occultation_profiles`, `SatelliteRetrieval` =>
+     * `retrieved_soundings`, `CycloneTrack` => `cyclone_tracks`, `ForecastSounding` =>
+     * `forecast_soundings`, and `Tables` => `LocalTables::add_message`, which collects the tables
+     * `forecast_soundings` needs.
+     */
+    pub fn has_extractor(&self) -> bool {
+        !matches!(self, Self::Template(_) | Self::Unknown)
+    }
+}
+
+impl Display for MessageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Self::Radiosonde {
+                template: template @ (RadiosondeTemplate::PilotPressure | RadiosondeTemplate::PilotHeight),
+                part: Some(part),
+            } => write!(f, "TAC PILOT part {:?} {}", part, template.code()),
+            Self::Radiosonde { template, part: Some(part) } => write!(f, "TAC TEMP part {:?} {}", part, template.code()),
+            Self::Radiosonde { template, part: None } => write!(f, "radiosonde {}", template.code()),
+            Self::Amdar => write!(f, "AMDAR"),
+            Self::Synop => write!(f, "SYNOP"),
+            Self::WindProfiler => write!(f, "wind profiler"),
+            Self::OceanProfile => write!(f, "ocean profile"),
+            Self::RadioOccultation => write!(f, "radio occultation"),
+            Self::SatelliteRetrieval => write!(f, "satellite retrieval"),
+            Self::CycloneTrack => write!(f, "tropical cyclone track"),
+            Self::ForecastSounding => write!(f, "forecast sounding"),
+            Self::Tables => write!(f, "BUFR tables"),
+            Self::Template(code) => write!(f, "template {}", code),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/** Classify a message by the sequences in section 3 and the data category in section 1, so it can
+ * be routed to the right extractor without looking at the data.
+ *
+ * The first section 3 descriptor that is a known template decides. Satellite soundings (data
+ * category 3) without a known template are retrievals, and tables (data category 11) are always
+ * tables. Other data messages with a local descriptor in section 3 are forecast soundings, the
+ * only local data this crate decodes. The parts of a TEMP or PILOT (309050 to 309052) are found from the levels of the first
+ * subset: parts A and C only have standard levels, B and D only significant levels, and parts C and
+ * D are above 100 hPa.
+ */
+pub fn classify_message(bufr: &BufrMessage) -> MessageKind {
+    if bufr.data_category() == TABLES_CATEGORY {
+        return MessageKind::Tables;
+    }
+
+    let mut first_sequence: Option<&str> = None;
+
+    for desc in bufr.descriptors() {
+        let desc = desc.as_str();
+
+        if let Some(template) = RadiosondeTemplate::from_code(desc) {
+            let part = match template {
+                RadiosondeTemplate::PilotPressure | RadiosondeTemplate::PilotHeight | RadiosondeTemplate::Temp => {
+                    temp_part(bufr, desc)
+                }
+                _ => None,
+            };
+            return MessageKind::Radiosonde { template, part };
+        }
+
+        let kind = if AIRCRAFT_SEQUENCES.contains(&desc) {
+            MessageKind::Amdar
+        } else if SYNOP_TEMPLATES.contains(&desc) {
+            MessageKind::Synop
+        } else if PROFILER_TEMPLATES.contains(&desc) {
+            MessageKind::WindProfiler
+        } else if OCEAN_TEMPLATES.contains(&desc) {
+            MessageKind::OceanProfile
+        } else if desc == OCCULTATION_TEMPLATE {
+            MessageKind::RadioOccultation
+        } else if desc == CYCLONE_TEMPLATE {
+            MessageKind::CycloneTrack
+        } else if is_local_descriptor(desc) {
+            MessageKind::ForecastSounding
+        } else {
+            if desc.starts_with('3') && first_sequence.is_none() {
+                first_sequence = Some(desc);
+            }
+            continue;
+        };
+
+        return kind;
+    }
+
+    if bufr.data_category() == SATELLITE_SOUNDINGS_CATEGORY {
+        return MessageKind::SatelliteRetrieval;
+    }
+
+    first_sequence
+        .map(|code| MessageKind::Template(code.to_owned()))
+        .unwrap_or(MessageKind::Unknown)
+}
+
+/** Local descriptors have X from 48 to 63 or Y from 192 to 255, e.g. "363218". */
+fn is_local_descriptor(desc: &str) -> bool {
+    let x: Option<u8> = desc.get(1..3).and_then(|x| x.parse().ok());
+    let y: Option<u8> = desc.get(3..6).and_then(|y| y.parse().ok());
+
+    x.is_some_and(|x| x >= 48) || y.is_some_and(|y| y >= 192)
+}
+
+/** Which part of a traditional alphanumeric report the levels of the template are, if only one. */
+fn temp_part(bufr: &BufrMessage, template: &str) -> Option<TempPart> {
+    let mut levels = Levels::default();
+    for structure in bufr.get_elements() {
+        if let Structure::Group(grp) = structure
+            && grp.code() == template
+        {
+            levels.collect(grp.items());
+        }
+    }
+
+    match (levels.low, levels.high, levels.standard, levels.significant) {
+        (true, false, true, false) => Some(TempPart::A),
+        (true, false, false, true) => Some(TempPart::B),
+        (false, true, true, false) => Some(TempPart::C),
+        (false, true, false, true) => Some(TempPart::D),
+        _ => None,
+    }
+}
+
+/** What kinds of levels a sounding has. */
+#[derive(Default)]
+struct Levels {
+    /** At or below 100 hPa */
+    low: bool,
+    /** Above 100 hPa */
+    high: bool,
+    standard: bool,
+    significant: bool,
+}
+
+impl Levels {
+    fn collect(&mut self, items: &[Structure]) {
+        for structure in items {
+            match structure {
+                Structure::Element(_) => {}
+                Structure::Group(grp) => self.collect(grp.items()),
+                Structure::Replication(rep) => {
+                    for block in rep.blocks() {
+                        self.add_level(block);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_level(&mut self, block: &[Structure]) {
+        let mut pressure: Option<f64> = None;
+        let mut significance: Option<VerticalSignificance> = None;
+        find_level_values(block, &mut pressure, &mut significance);
+
+        // Surface levels are in both parts A and B.
+        let significance = significance.unwrap_or_default();
+        if significance.is_surface() {
+            return;
+        }
+
+        match pressure {
+            Some(p) if p < PART_C_D_BELOW => self.high = true,
+            Some(_) => self.low = true,
+            None => return,
+        }
+
+        if significance.is_standard_level() {
+            self.standard = true;
+        }
+        if significance.is_significant_temperature()
+            || significance.is_significant_humidity()
+            || significance.is_significant_wind()
+        {
+            self.significant = true;
+        }
+    }
+}
+
+fn find_level_values(items: &[Structure], pressure: &mut Option<f64>, significance: &mut Option<VerticalSignificance>) {
+    for structure in items {
+        match structure {
+            Structure::Element(el) if el.code() == "007004" && pressure.is_none() => *pressure = el.get_f64_val(),
+            Structure::Element(el) if el.code() == "008042" && significance.is_none() => {
+                *significance = el.get_code_val().map(VerticalSignificance::from_008042)
+            }
+            Structure::Group(grp) => find_level_values(grp.items(), pressure, significance),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{find_bufr_start, read_bufr_message_from_slice, types::BufrMessageBuilder};
+
+    fn message(descriptors: &[&str], data_category: u8) -> BufrMessage {
+        let mut builder = BufrMessageBuilder::new();
+        builder
+            .bufr_version(4)
+            .bufr_master_table_version(30)
+            .data_category(data_category)
+            .descriptors(descriptors.iter().map(|&d| d.to_owned()).collect());
+        builder.build()
+    }
+
+    #[test]
+    fn test_classify_radiosonde() {
+        let bytes = include_bytes!("../../test-data/2017083115.bufr");
+        let start = find_bufr_start(bytes).unwrap();
+        let bufr = read_bufr_message_from_slice(&bytes[start..]).unwrap();
+
+        let kind = classify_message(&bufr);
+        assert_eq!(kind.to_string(), "radiosonde 309052");
+        assert!(kind.has_extractor());
+    }
+
+    #[test]
+    fn test_classify_local_and_unknown() {
+        // NCEP forecast profiles
+        assert_eq!(classify_message(&message(&["363218"], 102)), MessageKind::ForecastSounding);
+        assert_eq!(classify_message(&message(&["301192"], 0)), MessageKind::ForecastSounding);
+        assert!(MessageKind::ForecastSounding.has_extractor());
+
+        // Their DX tables use local sequences too, but are tables.
+        assert_eq!(classify_message(&message(&["360001", "360002"], 11)), MessageKind::Tables);
+
+        let kind = classify_message(&message(&["301011", "012101"], 0));
+        assert_eq!(kind, MessageKind::Template("301011".to_owned()));
+        assert!(!kind.has_extractor());
+
+        assert_eq!(classify_message(&message(&["012101"], 0)), MessageKind::Unknown);
+        assert_eq!(classify_message(&message(&["301011"], 3)), MessageKind::SatelliteRetrieval);
+    }
+}
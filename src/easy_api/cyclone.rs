@@ -0,0 +1,334 @@
+use std::error::Error;
+
+use chrono::{NaiveDateTime, TimeDelta};
+use metfor::{HectoPascal, Knots, MetersPSec};
+
+use super::{CodeValue, LaunchTime, extract_time_info};
+use crate::types::{BufrMessage, Element, Structure};
+
+/** Tropical cyclone track and wind radii template. */
+pub(super) const CYCLONE_TEMPLATE: &str = "316082";
+
+/** Meteorological attribute significance (008005) for the location of the maximum wind. */
+const MAX_WIND_LOCATION: u64 = 3;
+
+/** A point on a tropical cyclone track, the analysis or a forecast. */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TrackPoint {
+    pub(super) time: Option<NaiveDateTime>,
+    pub(super) lead_time: i32,
+    pub(super) location: Option<(f64, f64)>,
+    pub(super) central_pressure: Option<HectoPascal>,
+    pub(super) max_wind: Option<Knots>,
+    pub(super) max_wind_location: Option<(f64, f64)>,
+}
+
+impl TrackPoint {
+    /** Valid time of the point. */
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        self.time
+    }
+
+    /** Hours after the analysis (004024), zero for the analysis itself. */
+    pub fn lead_time(&self) -> i32 {
+        self.lead_time
+    }
+
+    /** Latitude and longitude of the storm centre. */
+    pub fn location(&self) -> Option<(f64, f64)> {
+        self.location
+    }
+
+    /** Pressure reduced to mean sea level at the storm centre (010051). */
+    pub fn central_pressure(&self) -> Option<HectoPascal> {
+        self.central_pressure
+    }
+
+    /** Maximum wind speed at 10 m (011012). */
+    pub fn max_wind(&self) -> Option<Knots> {
+        self.max_wind
+    }
+
+    /** Latitude and longitude of the maximum wind. */
+    pub fn max_wind_location(&self) -> Option<(f64, f64)> {
+        self.max_wind_location
+    }
+}
+
+/** The track of a tropical cyclone from one forecast, which is one member of an ensemble. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CycloneTrack {
+    pub(super) storm_id: Option<String>,
+    pub(super) storm_name: Option<String>,
+    pub(super) ensemble_member: Option<u32>,
+    pub(super) ensemble_type: Option<CodeValue>,
+    pub(super) observed_location: Option<(f64, f64)>,
+    pub(super) points: Vec<TrackPoint>,
+}
+
+impl CycloneTrack {
+    /** Storm identifier (001025), e.g. "09L". */
+    pub fn storm_id(&self) -> Option<&str> {
+        self.storm_id.as_deref()
+    }
+
+    /** WMO long storm name (001027). */
+    pub fn storm_name(&self) -> Option<&str> {
+        self.storm_name.as_deref()
+    }
+
+    /** Ensemble member number (001091), 0 is usually the control forecast. */
+    pub fn ensemble_member(&self) -> Option<u32> {
+        self.ensemble_member
+    }
+
+    /** Type of ensemble forecast (001092), e.g. the control or a perturbed forecast. */
+    pub fn ensemble_type(&self) -> Option<CodeValue> {
+        self.ensemble_type
+    }
+
+    /** Latitude and longitude of the storm centre in the warning the forecast was started from. */
+    pub fn observed_location(&self) -> Option<(f64, f64)> {
+        self.observed_location
+    }
+
+    /** The analysis followed by the forecasts, in the order they were reported. Forecasts after the
+     * storm was lost, which have no location, are left out.
+     */
+    pub fn points(&self) -> &[TrackPoint] {
+        &self.points
+    }
+}
+
+/** Get the tropical cyclone track (316082) of every forecast, usually one per subset, in a message.
+ *
+ * The analysis comes from the values before the forecast replication, and each repetition of the
+ * replication is a forecast that many hours (004024) after the analysis. The analysis has the
+ * observed storm centre followed by the centre in the model analysis, so the storm centre is the
+ * last location that isn't the location of the maximum wind. The wind radii are not decoded.
+ */
+pub fn cyclone_tracks(bufr: &BufrMessage) -> Result<Vec<CycloneTrack>, Box<dyn Error>> {
+    let mut tracks = vec![];
+
+    for subset in bufr.subsets() {
+        for structure in subset {
+            if let Structure::Group(grp) = structure
+                && grp.code() == CYCLONE_TEMPLATE
+            {
+                tracks.push(track_from_template(grp.items()));
+            }
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No tropical cyclone track template in message.",
+        )));
+    }
+
+    Ok(tracks)
+}
+
+fn track_from_template(items: &[Structure]) -> CycloneTrack {
+    let mut time = LaunchTime::default();
+    extract_time_info(items, &mut time);
+    let analysis_time = time.to_datetime();
+
+    let text = |el: &Element| {
+        el.get_str_val()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+    };
+
+    let mut track = CycloneTrack::default();
+    for structure in items {
+        if let Structure::Element(el) = structure {
+            match el.code() {
+                "001025" => track.storm_id = text(el),
+                "001027" => track.storm_name = text(el),
+                "001091" => track.ensemble_member = el.get_u32_val(),
+                "001092" => track.ensemble_type = CodeValue::from_element(el),
+                _ => {}
+            }
+        }
+    }
+
+    let mut analysis = PointBuilder::default();
+    analysis.add_all(items);
+    track.observed_location = analysis.first_location;
+    track.points.extend(analysis.build(analysis_time));
+
+    // The wind radii are in fixed replications, the forecasts in the delayed one.
+    for structure in items {
+        if let Structure::Replication(rep) = structure {
+            for block in rep.blocks() {
+                let mut forecast = PointBuilder::default();
+                forecast.add_all(block);
+                if forecast.period.is_some() {
+                    track.points.extend(forecast.build(analysis_time));
+                }
+            }
+        }
+    }
+
+    track
+}
+
+/** Collects the values of a track point in the order they were reported. */
+#[derive(Default)]
+struct PointBuilder {
+    period: Option<i32>,
+    significance: Option<u64>,
+    lat: Option<f64>,
+    first_location: Option<(f64, f64)>,
+    location: Option<(f64, f64)>,
+    central_pressure: Option<HectoPascal>,
+    max_wind: Option<Knots>,
+    max_wind_location: Option<(f64, f64)>,
+}
+
+impl PointBuilder {
+    /** Add the elements, including those in sequences, but not those in replications. */
+    fn add_all(&mut self, items: &[Structure]) {
+        for structure in items {
+            match structure {
+                Structure::Element(el) => self.add(el),
+                Structure::Group(grp) => self.add_all(grp.items()),
+                Structure::Replication(_) => {}
+            }
+        }
+    }
+
+    fn add(&mut self, el: &Element) {
+        match el.code() {
+            "004024" => self.period = el.get_i32_val(),
+            "008005" => self.significance = el.get_code_val(),
+            "005002" => self.lat = el.get_f64_val(),
+            "006002" => {
+                let location = self.lat.take().zip(el.get_f64_val());
+                if self.significance == Some(MAX_WIND_LOCATION) {
+                    self.max_wind_location = location;
+                } else if location.is_some() {
+                    self.first_location = self.first_location.or(location);
+                    self.location = location;
+                }
+            }
+            "010051" => self.central_pressure = el.get_f64_val().map(|p| HectoPascal(p / 100.0)),
+            "011012" => self.max_wind = el.get_f64_val().map(MetersPSec).map(Knots::from),
+            _ => {}
+        }
+    }
+
+    fn build(&self, analysis_time: Option<NaiveDateTime>) -> Option<TrackPoint> {
+        let lead_time = self.period.unwrap_or(0);
+
+        Some(TrackPoint {
+            time: analysis_time.map(|t| t + TimeDelta::hours(i64::from(lead_time))),
+            lead_time,
+            location: Some(self.location?),
+            central_pressure: self.central_pressure,
+            max_wind: self.max_wind,
+            max_wind_location: self.max_wind_location,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Replication, Value};
+    use chrono::NaiveDate;
+
+    fn element(fxy: &'static str, val: Value) -> Structure {
+        Structure::Element(Element::new(val, "", "", fxy))
+    }
+
+    fn float(fxy: &'static str, val: Option<f64>) -> Structure {
+        element(fxy, val.map_or(Value::Missing, Value::Float))
+    }
+
+    /** The storm centre or maximum wind location, with the meteorological attribute significance. */
+    fn location(significance: u64, lat: Option<f64>, lon: Option<f64>) -> [Structure; 3] {
+        [
+            element("008005", Value::Code(significance)),
+            float("005002", lat),
+            float("006002", lon),
+        ]
+    }
+
+    /** A forecast in the delayed replication of 316082. */
+    fn forecast(hours: i64, centre: [Structure; 3], max_wind: [Structure; 3]) -> Vec<Structure> {
+        let mut block = vec![
+            element("008021", Value::Code(4)),
+            element("004024", Value::Numeric(hours)),
+        ];
+        block.extend(centre);
+        block.push(float("010051", Some(95_000.0)));
+        block.extend(max_wind);
+        block.push(float("011012", Some(60.0)));
+        block
+    }
+
+    #[test]
+    fn test_track_from_template() {
+        let mut items = vec![
+            element("001025", Value::Str("09L".to_owned())),
+            element("001027", Value::Str("IRMA      ".to_owned())),
+            element("001091", Value::Numeric(1)),
+            element("004001", Value::Numeric(2017)),
+            element("004002", Value::Numeric(9)),
+            element("004003", Value::Numeric(5)),
+            element("004004", Value::Numeric(0)),
+            element("004005", Value::Numeric(0)),
+        ];
+        // The observed storm centre, then the centre in the analysis, then the maximum wind.
+        items.extend(location(1, Some(17.0), Some(-55.0)));
+        items.extend(location(4, Some(17.2), Some(-55.4)));
+        items.push(float("010051", Some(93_000.0)));
+        items.extend(location(3, Some(17.5), Some(-55.5)));
+        items.push(float("011012", Some(70.0)));
+
+        let mut rep = Replication::new_with_capacity(30, 10);
+        let forecasts = [
+            forecast(12, location(1, Some(18.0), Some(-57.0)), location(3, Some(18.5), Some(-56.5))),
+            // The maximum wind comes before the storm centre.
+            forecast(24, location(3, Some(19.5), Some(-57.5)), location(1, Some(19.0), Some(-58.0))),
+            // The storm was lost.
+            forecast(36, location(1, None, None), location(3, Some(20.5), Some(-58.5))),
+        ];
+        for item in forecasts.into_iter().flatten() {
+            rep.push(item);
+        }
+        items.push(Structure::Replication(rep));
+
+        let track = track_from_template(&items);
+        assert_eq!(track.storm_id(), Some("09L"));
+        assert_eq!(track.storm_name(), Some("IRMA"));
+        assert_eq!(track.ensemble_member(), Some(1));
+        assert_eq!(track.observed_location(), Some((17.0, -55.0)));
+
+        let points = track.points();
+        assert_eq!(points.len(), 3);
+
+        let analysis_time = NaiveDate::from_ymd_opt(2017, 9, 5).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let analysis = &points[0];
+        assert_eq!(analysis.time(), Some(analysis_time));
+        assert_eq!(analysis.lead_time(), 0);
+        assert_eq!(analysis.location(), Some((17.2, -55.4)));
+        assert_eq!(analysis.max_wind_location(), Some((17.5, -55.5)));
+        assert_eq!(analysis.central_pressure(), Some(HectoPascal(930.0)));
+        assert_eq!(analysis.max_wind(), Some(Knots::from(MetersPSec(70.0))));
+
+        assert_eq!(points[1].time(), Some(analysis_time + TimeDelta::hours(12)));
+        assert_eq!(points[1].lead_time(), 12);
+        assert_eq!(points[1].location(), Some((18.0, -57.0)));
+        assert_eq!(points[1].max_wind_location(), Some((18.5, -56.5)));
+        assert_eq!(points[1].central_pressure(), Some(HectoPascal(950.0)));
+
+        assert_eq!(points[2].lead_time(), 24);
+        assert_eq!(points[2].location(), Some((19.0, -58.0)));
+        assert_eq!(points[2].max_wind_location(), Some((19.5, -57.5)));
+    }
+}
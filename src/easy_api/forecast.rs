@@ -0,0 +1,329 @@
+use std::{collections::HashMap, error::Error, path::Path};
+
+use chrono::TimeDelta;
+use metfor::{Celsius, HectoPascal, Kelvin, Knots, Meters, MetersPSec, Mm, PaPS, WindSpdDir, WindUV};
+use optional::{Optioned, none};
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::Moisture;
+use crate::{
+    find_message, read_bufr_message_from_slice_with_tables,
+    tables::local::LocalTables,
+    types::{BufrMessage, Element, Structure},
+};
+
+/** The first element with each mnemonic. */
+type Values<'a> = HashMap<&'static str, &'a Element>;
+
+/** Load every forecast sounding in a file of NCEP model profiles, using the DX tables at the start
+ * of the file to decode the rest of it. See `forecast_soundings`.
+ *
+ * Every message after the tables has to be a forecast profile. A message that is cut short, can't be
+ * decoded, or has no profiles is an error, so a corrupt file doesn't come back with soundings
+ * missing.
+ */
+pub fn load_forecast_soundings(path: &Path) -> Result<Vec<Sounding>, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    let mut rest = bytes.as_slice();
+
+    let mut tables = LocalTables::new();
+    let mut soundings = vec![];
+
+    while !rest.is_empty() {
+        let (skipped, message) = match find_message(rest) {
+            Some((start, message)) => (&rest[..start], Some(message)),
+            None => (rest, None),
+        };
+
+        // A start of a message that wasn't framed by its length is a corrupt or cut short message.
+        if skipped.windows(4).any(|window| window == b"BUFR") {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Corrupt or cut short message in file.",
+            )));
+        }
+
+        let message = match message {
+            Some(message) => message,
+            None => break,
+        };
+
+        let bufr = read_bufr_message_from_slice_with_tables(message, &tables)?;
+        if LocalTables::is_tables_message(&bufr) {
+            tables.add_message(&bufr)?;
+        } else {
+            soundings.append(&mut forecast_soundings(&bufr, &tables)?);
+        }
+
+        rest = &rest[(skipped.len() + message.len())..];
+    }
+
+    if soundings.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No forecast soundings in file.",
+        )));
+    }
+
+    Ok(soundings)
+}
+
+/** Build a sounding for every subset of an NCEP model forecast profile message, like the GFS and
+ * NAM point forecasts (class 1 profiles) that Bufkit uses.
+ *
+ * Each subset is one station at one forecast hour. These messages only use local descriptors, so
+ * the elements are found by their mnemonics in `tables`, which needs the DX tables from the start
+ * of the file. The levels are the repetitions of any replication with a pressure (PRES), with the
+ * temperature (TMDB), specific humidity (SPFH), wind components (UWND, VWND), vertical velocity
+ * (OMEG), cloud fraction (CFRL), and height (HGHT or GEOP) if there is one. The lead time is the
+ * forecast hour (FHR), and the valid time is that many hours after the time in section 1, which is
+ * the model initialization time.
+ */
+pub fn forecast_soundings(bufr: &BufrMessage, tables: &LocalTables) -> Result<Vec<Sounding>, Box<dyn Error>> {
+    let soundings: Vec<Sounding> = bufr
+        .subsets()
+        .iter()
+        .filter_map(|subset| sounding_from_subset(bufr, tables, subset))
+        .collect();
+
+    if soundings.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No forecast profiles in message.",
+        )));
+    }
+
+    Ok(soundings)
+}
+
+fn sounding_from_subset(bufr: &BufrMessage, tables: &LocalTables, items: &[Structure]) -> Option<Sounding> {
+    let mut header = Values::new();
+    let mut levels: Vec<Values> = vec![];
+    collect(items, tables, &mut header, &mut levels);
+
+    if levels.is_empty() {
+        return None;
+    }
+
+    let lead_time = number(&header, &["FHR"]).map(|h| h.round() as i32);
+
+    let mut station = StationInfo::new();
+    if let Some(num) = number(&header, &["STNM", "WMOS"]) {
+        station = station.with_station(num as i32);
+    }
+    let id = header
+        .get("STID")
+        .and_then(|el| el.get_str_val())
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(String::from);
+    if id.is_some() {
+        station = station.with_station_id(id);
+    }
+    if let Some(location) = number(&header, &["CLAT", "SLAT"]).zip(number(&header, &["CLON", "SLON"])) {
+        station = station.with_lat_lon(location);
+    }
+    if let Some(elevation) = number(&header, &["SELV", "GELV", "HSFC"]) {
+        station = station.with_elevation(Meters(elevation));
+    }
+
+    let mut snd = Sounding::new()
+        .with_station_info(station)
+        .with_lead_time(Optioned::<i32>::from(lead_time));
+    if let Some(vt) = bufr.typical_time() {
+        snd = snd.with_valid_time(vt + TimeDelta::hours(i64::from(lead_time.unwrap_or(0))));
+    }
+
+    let kelvin = |values: &Values, mnemonics| number(values, mnemonics).map(Kelvin).map(Celsius::from);
+    let wind = |values: &Values, u, v| -> Optioned<WindSpdDir<Knots>> {
+        match number(values, &[u]).zip(number(values, &[v])) {
+            Some((u, v)) => WindSpdDir::<Knots>::from(WindUV {
+                u: MetersPSec(u),
+                v: MetersPSec(v),
+            })
+            .into(),
+            None => none(),
+        }
+    };
+    let dew_point = |values: &Values, specific_humidity, temperature, pressure| {
+        let moisture = Moisture {
+            specific_humidity: number(values, &[specific_humidity]),
+            ..Moisture::default()
+        };
+        moisture.dew_point(temperature, pressure).map(|(td, _)| td).into()
+    };
+
+    let pres: Vec<Optioned<HectoPascal>> = levels.iter().map(|lvl| pressure_at(lvl, "PRES").into()).collect();
+    let temp: Vec<Optioned<Celsius>> = levels.iter().map(|lvl| kelvin(lvl, &["TMDB"]).into()).collect();
+    let dewp = levels
+        .iter()
+        .zip(temp.iter().zip(&pres))
+        .map(|(lvl, (&t, &p))| dew_point(lvl, "SPFH", t, p))
+        .collect();
+
+    let sfc_pressure: Optioned<HectoPascal> = pressure_at(&header, "PRSS").into();
+    let sfc_temperature: Optioned<Celsius> = kelvin(&header, &["T2MS"]).into();
+
+    snd = snd
+        .with_pressure_profile(pres)
+        .with_temperature_profile(temp)
+        .with_dew_point_profile(dewp)
+        .with_wind_profile(levels.iter().map(|lvl| wind(lvl, "UWND", "VWND")).collect())
+        .with_pvv_profile(levels.iter().map(|lvl| number(lvl, &["OMEG"]).map(PaPS).into()).collect())
+        .with_cloud_fraction_profile(levels.iter().map(|lvl| number(lvl, &["CFRL"]).into()).collect())
+        .with_mslp(Optioned::<HectoPascal>::from(pressure_at(&header, "PMSL")))
+        .with_station_pressure(sfc_pressure)
+        .with_sfc_temperature(sfc_temperature)
+        .with_sfc_dew_point(dew_point(&header, "Q2MS", sfc_temperature, sfc_pressure))
+        .with_sfc_wind(wind(&header, "U10M", "V10M"))
+        .with_precipitation(Optioned::<Mm>::from(number(&header, &["P01M"]).map(Mm)))
+        .with_low_cloud(Optioned::<f64>::from(number(&header, &["LCLD"]).map(|c| c / 100.0)))
+        .with_mid_cloud(Optioned::<f64>::from(number(&header, &["MCLD"]).map(|c| c / 100.0)))
+        .with_high_cloud(Optioned::<f64>::from(number(&header, &["HCLD"]).map(|c| c / 100.0)));
+
+    if levels.iter().any(|lvl| number(lvl, &["HGHT", "GEOP"]).is_some()) {
+        snd = snd.with_height_profile(
+            levels
+                .iter()
+                .map(|lvl| number(lvl, &["HGHT", "GEOP"]).map(Meters).into())
+                .collect(),
+        );
+    }
+
+    Some(snd)
+}
+
+/** Collect the values outside the replications into `header`, and the values of each repetition
+ * with a pressure into `levels`.
+ */
+fn collect<'a>(items: &'a [Structure], tables: &LocalTables, header: &mut Values<'a>, levels: &mut Vec<Values<'a>>) {
+    for structure in items {
+        match structure {
+            Structure::Element(el) => {
+                if let Some(mnemonic) = tables.mnemonic(el.code()) {
+                    header.entry(mnemonic).or_insert(el);
+                }
+            }
+            Structure::Group(grp) => collect(grp.items(), tables, header, levels),
+            Structure::Replication(rep) => {
+                for block in rep.blocks() {
+                    let mut level = Values::new();
+                    collect(block, tables, &mut level, levels);
+                    if number(&level, &["PRES"]).is_some() {
+                        levels.push(level);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/** The value of the first of these mnemonics that has one. */
+fn number(values: &Values, mnemonics: &[&str]) -> Option<f64> {
+    mnemonics.iter().find_map(|mnemonic| {
+        let el = values.get(mnemonic)?;
+        el.get_f64_val().or_else(|| el.get_i32_val().map(f64::from))
+    })
+}
+
+fn pressure_at(values: &Values, mnemonic: &str) -> Option<HectoPascal> {
+    number(values, &[mnemonic]).map(|p| HectoPascal(p / 100.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{Bits, DxElement, DxSequence, dx_tables, message};
+
+    const ELEMENTS: [DxElement; 9] = [
+        ("063001", "FHR     Forecast hour", "HOURS", 0, 0, 10),
+        ("063002", "STID    Station identifier", "CCITT IA5", 0, 0, 64),
+        ("063003", "STNM    Station number", "NUMERIC", 0, 0, 20),
+        ("063010", "PRES    Pressure", "PASCALS", -1, 0, 14),
+        ("063011", "TMDB    Temperature", "DEGREES KELVIN", 1, 0, 12),
+        ("063012", "SPFH    Specific humidity", "KG/KG", 5, 0, 14),
+        ("063013", "UWND    U wind", "METERS/SECOND", 1, -4096, 13),
+        ("063014", "VWND    V wind", "METERS/SECOND", 1, -4096, 13),
+        ("063021", "PRSS    Surface pressure", "PASCALS", -1, 0, 14),
+    ];
+
+    const SEQUENCES: [DxSequence; 2] = [
+        (
+            "363000",
+            "CLASS1  Model sounding",
+            &["063001", "063002", "063003", "063021", "101000", "031001", "363002"],
+        ),
+        ("363002", "PROFILE Profile level", &["063010", "063011", "063012", "063013", "063014"]),
+    ];
+
+    /** A file with the DX tables and a message with a sounding for each forecast hour. */
+    fn file(hours: &[f64]) -> Vec<u8> {
+        let mut bytes = dx_tables(&ELEMENTS, &SEQUENCES);
+
+        let bufr = crate::read_bufr_message_from_slice(&bytes).unwrap();
+        let mut tables = LocalTables::new();
+        tables.add_message(&bufr).unwrap();
+
+        let mut data = Bits::with_tables(tables);
+        for &hour in hours {
+            data.element("063001", Some(hour))
+                .text("063002", "KMSO")
+                .element("063003", Some(727730.0))
+                .element("063021", Some(90_520.0))
+                .element("031001", Some(2.0));
+            for (p, t, u) in [(90_000.0, 290.0, Some(5.0)), (85_000.0, 287.0, None)] {
+                data.element("063010", Some(p))
+                    .element("063011", Some(t))
+                    .element("063012", Some(0.005))
+                    .element("063013", u)
+                    .element("063014", u.map(|_| 0.0));
+            }
+        }
+
+        bytes.extend(message(&["363000"], &data, hours.len() as u16, 102, false));
+        bytes
+    }
+
+    fn load(name: &str, bytes: &[u8]) -> Result<Vec<Sounding>, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("{}-{}.bufr", name, std::process::id()));
+        std::fs::write(&path, bytes)?;
+        let soundings = load_forecast_soundings(&path);
+        std::fs::remove_file(&path)?;
+        soundings
+    }
+
+    #[test]
+    fn test_load_forecast_soundings() {
+        let soundings = load("forecast-soundings", &file(&[0.0, 3.0])).unwrap();
+        assert_eq!(soundings.len(), 2);
+
+        let snd = &soundings[1];
+        assert_eq!(snd.lead_time().into_option(), Some(3));
+        assert_eq!(snd.valid_time().map(|vt| vt.to_string()).as_deref(), Some("2020-01-01 15:00:00"));
+        assert_eq!(snd.station_info().station_num().into_option(), Some(727730));
+        assert_eq!(snd.station_info().station_id(), Some("KMSO"));
+        assert_eq!(snd.station_pressure().into_option(), Some(HectoPascal(905.2)));
+
+        let pressures: Vec<Option<HectoPascal>> = snd.pressure_profile().iter().map(|p| p.into_option()).collect();
+        assert_eq!(pressures, vec![Some(HectoPascal(905.2)), Some(HectoPascal(900.0)), Some(HectoPascal(850.0))]);
+        assert!(snd.temperature_profile()[1].into_option().is_some_and(|t| (t.0 - 16.85).abs() < 1e-9));
+        assert!(snd.dew_point_profile()[1].into_option().is_some_and(|td| td < Celsius(16.85)));
+        assert!(snd.wind_profile()[1].is_some());
+        assert!(snd.wind_profile()[2].is_none());
+        assert!(snd.height_profile().is_empty());
+    }
+
+    #[test]
+    fn test_load_forecast_soundings_errors() {
+        let bytes = file(&[0.0]);
+
+        // The data message is cut short.
+        let err = load("forecast-cut-short", &bytes[..bytes.len() - 10]).unwrap_err();
+        assert_eq!(err.to_string(), "Corrupt or cut short message in file.");
+
+        // Only the tables
+        let tables = dx_tables(&ELEMENTS, &SEQUENCES);
+        let err = load("forecast-tables-only", &tables).unwrap_err();
+        assert_eq!(err.to_string(), "No forecast soundings in file.");
+    }
+}
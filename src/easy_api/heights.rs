@@ -0,0 +1,192 @@
+use metfor::{HectoPascal, Kelvin, Meters, Rd, g, virtual_temperature};
+use optional::Optioned;
+
+use super::RadiosondeReport;
+
+impl RadiosondeReport {
+    /** Compute missing geopotential heights with the hypsometric equation and return how many
+     * were filled in.
+     *
+     * The layer between each missing height and the nearest level below it with a height is
+     * integrated using the mean virtual temperature of the two levels, starting from the station
+     * elevation. Levels below the lowest reported height, when the station elevation is missing,
+     * are integrated down from the nearest level above instead. The dew point is used for the
+     * virtual temperature when it's available, otherwise just the temperature. Levels without a
+     * pressure, or without a temperature at either end of the layer, are left missing.
+     *
+     * Computed heights are marked with `LevelInfo::height_is_derived`. Run this after
+     * `quality_control` so inconsistent reported heights aren't used as anchors.
+     */
+    pub fn fill_missing_heights(&mut self) -> usize {
+        let snd = &self.sounding;
+        let pres = snd.pressure_profile();
+        let mut hgt: Vec<Optioned<Meters>> = snd.height_profile().to_vec();
+
+        let tv: Vec<Option<Kelvin>> = (0..pres.len())
+            .map(|i| {
+                let t = snd.temperature_profile().get(i)?.into_option()?;
+                let td = snd.dew_point_profile().get(i).and_then(|td| td.into_option());
+                let p = pres[i].into_option();
+
+                td.zip(p)
+                    .and_then(|(td, p)| virtual_temperature(t, td, p))
+                    .or_else(|| Some(Kelvin::from(t)))
+            })
+            .collect();
+
+        let layer_top = |anchor: usize, hgt: &[Optioned<Meters>], i: usize| -> Option<Meters> {
+            let (p0, z0) = pres[anchor].into_option().zip(hgt[anchor].into_option())?;
+            let p = pres[i].into_option()?;
+            hypsometric_height(p0, z0, tv[anchor], p, tv[i])
+        };
+
+        let mut filled = vec![false; hgt.len()];
+
+        // Up from the station elevation or the lowest reported height. The surface height is the
+        // station elevation, so it's never filled in.
+        let mut anchor: Option<usize> = None;
+        for i in 0..hgt.len() {
+            if hgt[i].is_some() {
+                if pres[i].is_some() {
+                    anchor = Some(i);
+                }
+            } else if i > 0
+                && let Some(z) = anchor.and_then(|a| layer_top(a, &hgt, i))
+            {
+                hgt[i] = z.into();
+                filled[i] = true;
+                anchor = Some(i);
+            }
+        }
+
+        // Down from the nearest level above for anything below the first anchor.
+        let mut anchor: Option<usize> = None;
+        for i in (1..hgt.len()).rev() {
+            if hgt[i].is_some() {
+                if pres[i].is_some() {
+                    anchor = Some(i);
+                }
+            } else if let Some(z) = anchor.and_then(|a| layer_top(a, &hgt, i)) {
+                hgt[i] = z.into();
+                filled[i] = true;
+                anchor = Some(i);
+            }
+        }
+
+        let count = filled.iter().filter(|&&f| f).count();
+        if count == 0 {
+            return 0;
+        }
+
+        for (lvl, &f) in self.levels.iter_mut().zip(&filled) {
+            lvl.derived_height |= f;
+        }
+
+        // The surface value is put back in by the sounding.
+        let snd = std::mem::take(&mut self.sounding);
+        self.sounding = snd.with_height_profile(hgt.into_iter().skip(1).collect());
+
+        count
+    }
+}
+
+/** Height at pressure `p` given the height `z0` at pressure `p0`, using the mean virtual
+ * temperature of whichever ends of the layer have one.
+ */
+fn hypsometric_height(
+    p0: HectoPascal,
+    z0: Meters,
+    tv0: Option<Kelvin>,
+    p: HectoPascal,
+    tv: Option<Kelvin>,
+) -> Option<Meters> {
+    let mean_tv = match (tv0, tv) {
+        (Some(a), Some(b)) => (a.0 + b.0) / 2.0,
+        (Some(t), None) | (None, Some(t)) => t.0,
+        (None, None) => return None,
+    };
+
+    // metfor's gravity is negative, pointing down.
+    let thickness = Rd.0 / -g * mean_tv * (p0.0 / p.0).ln();
+
+    Some(Meters(z0.0 + thickness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::easy_api::{LevelInfo, RadiosondeMetadata};
+    use metfor::Celsius;
+    use sounding_analysis::{Sounding, StationInfo};
+
+    /** Pressure, temperature, and height above the surface. */
+    fn report(elevation: Option<f64>, levels: &[(Option<f64>, Option<f64>, Option<f64>)]) -> RadiosondeReport {
+        let mut station = StationInfo::new();
+        if let Some(elevation) = elevation {
+            station = station.with_elevation(Meters(elevation));
+        }
+
+        let sounding = Sounding::new()
+            .with_station_info(station)
+            .with_station_pressure(HectoPascal(900.0))
+            .with_sfc_temperature(Celsius(20.0))
+            .with_pressure_profile(levels.iter().map(|lvl| lvl.0.map(HectoPascal).into()).collect())
+            .with_temperature_profile(levels.iter().map(|lvl| lvl.1.map(Celsius).into()).collect())
+            .with_height_profile(levels.iter().map(|lvl| lvl.2.map(Meters).into()).collect());
+
+        RadiosondeReport {
+            sounding,
+            levels: vec![LevelInfo::default(); levels.len() + 1],
+            metadata: RadiosondeMetadata::default(),
+            wind_shear: vec![],
+            wigos_id: None,
+        }
+    }
+
+    fn heights(report: &RadiosondeReport) -> Vec<Option<f64>> {
+        report.sounding().height_profile().iter().map(|z| z.into_option().map(|z| z.0)).collect()
+    }
+
+    fn derived(report: &RadiosondeReport) -> Vec<bool> {
+        report.levels().iter().map(|lvl| lvl.height_is_derived()).collect()
+    }
+
+    #[test]
+    fn test_fill_missing_heights() {
+        let mut report = report(
+            Some(1000.0),
+            &[
+                (Some(850.0), Some(15.0), None),
+                (Some(700.0), Some(0.0), Some(3100.0)),
+                (Some(500.0), Some(-15.0), None),
+                (None, Some(-20.0), None),
+            ],
+        );
+
+        assert_eq!(report.fill_missing_heights(), 2);
+
+        let z = heights(&report);
+        assert_eq!(z[0], Some(1000.0));
+        assert!(z[1].is_some_and(|z| (z - 1486.29).abs() < 0.01));
+        assert_eq!(z[2], Some(3100.0));
+        assert!(z[3].is_some_and(|z| (z - 5716.42).abs() < 0.01));
+        assert_eq!(z[4], None);
+        assert_eq!(derived(&report), vec![false, true, false, true, false]);
+
+        // Nothing left to fill in.
+        assert_eq!(report.fill_missing_heights(), 0);
+    }
+
+    #[test]
+    fn test_fill_missing_heights_down() {
+        // Without the station elevation the levels below the lowest height are integrated down.
+        let mut report = report(None, &[(Some(850.0), Some(15.0), None), (Some(700.0), Some(0.0), Some(3100.0))]);
+
+        assert_eq!(report.fill_missing_heights(), 1);
+
+        let z = heights(&report);
+        assert_eq!(z[0], None);
+        assert!(z[1].is_some_and(|z| (z - 1504.98).abs() < 0.01));
+        assert_eq!(derived(&report), vec![false, true, false]);
+    }
+}
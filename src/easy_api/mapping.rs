@@ -0,0 +1,349 @@
+use std::error::Error;
+
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::{LaunchTime, Profiles, StationIdentity, Surface, extract_station_location, extract_time_info};
+use crate::types::{BufrMessage, Structure};
+
+/** Which elements hold a variable and how to convert them to the units the `Sounding` uses.
+ *
+ * The converted value is `value * scale + offset`, where `value` is in the Table B units. The
+ * first code in the list with a value in a level is used.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElementMapping {
+    codes: Vec<String>,
+    scale: f64,
+    offset: f64,
+}
+
+impl ElementMapping {
+    /** Map these element descriptors (e.g. "012101") with no unit conversion. */
+    pub fn new(codes: &[&str]) -> Self {
+        ElementMapping {
+            codes: codes.iter().map(|&c| c.to_owned()).collect(),
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    /** Multiply the value by `scale`, e.g. 0.01 for Pa to hPa. */
+    pub fn with_scale(self, scale: f64) -> Self {
+        ElementMapping { scale, ..self }
+    }
+
+    /** Add `offset` after scaling, e.g. -273.15 for K to °C. */
+    pub fn with_offset(self, offset: f64) -> Self {
+        ElementMapping { offset, ..self }
+    }
+
+    /** Get the element descriptors. */
+    pub fn codes(&self) -> &[String] {
+        &self.codes
+    }
+
+    fn value(&self, level: &[Structure]) -> Option<f64> {
+        self.codes.iter().find_map(|code| {
+            level.iter().find_map(|s| match s {
+                Structure::Element(el) if el.code() == code => el.get_f64_val(),
+                _ => None,
+            })
+        })
+    }
+
+    fn convert(&self, level: &[Structure]) -> Option<f64> {
+        // Dividing by 100 gives the closest value to 93.1 hPa for 9310 Pa, multiplying by 0.01
+        // doesn't.
+        let divisor = 1.0 / self.scale;
+        let scaled = |v: f64| if divisor.fract() == 0.0 { v / divisor } else { v * self.scale };
+
+        self.value(level).map(|v| scaled(v) + self.offset)
+    }
+}
+
+/** The converted values of the mapped variables in a single level. */
+#[derive(Default)]
+pub(super) struct LevelValues {
+    pub(super) pres: Option<f64>,
+    pub(super) temp: Option<f64>,
+    pub(super) dewp: Option<f64>,
+    pub(super) hgt: Option<f64>,
+    pub(super) dir: Option<f64>,
+    pub(super) spd: Option<f64>,
+    pub(super) omega: Option<f64>,
+    pub(super) cloud: Option<f64>,
+}
+
+/** A declarative description of where the levels of a profile are and how to read them.
+ *
+ * The levels are the repetitions of any replication in the message whose replicated descriptor is
+ * one of the level sequences. With no level sequences every repetition of every replication is a
+ * level, for templates that replicate the level elements directly. The converted values must be
+ * in hPa, °C, meters, degrees, m/s, Pa/s, and a cloud fraction from 0 to 1.
+ *
+ * ```no_run
+ * # use sonde_bufr::{ElementMapping, ProfileMapping};
+ * let mapping = ProfileMapping::new(&["303054"])
+ *     .with_pressure(ElementMapping::new(&["007004"]).with_scale(0.01))
+ *     .with_temperature(ElementMapping::new(&["012101"]).with_offset(-273.15));
+ * ```
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileMapping {
+    level_sequences: Vec<String>,
+    pressure: Option<ElementMapping>,
+    temperature: Option<ElementMapping>,
+    dew_point: Option<ElementMapping>,
+    height: Option<ElementMapping>,
+    wind_direction: Option<ElementMapping>,
+    wind_speed: Option<ElementMapping>,
+    omega: Option<ElementMapping>,
+    cloud_fraction: Option<ElementMapping>,
+}
+
+impl ProfileMapping {
+    /** Create a mapping for levels in these Table D sequences, with no variables mapped yet. */
+    pub fn new(level_sequences: &[&str]) -> Self {
+        ProfileMapping {
+            level_sequences: level_sequences.iter().map(|&c| c.to_owned()).collect(),
+            ..ProfileMapping::default()
+        }
+    }
+
+    /** The mapping for the levels of all the `RadiosondeTemplate`s. */
+    pub fn radiosonde() -> Self {
+        ProfileMapping::new(&super::LEVEL_SEQUENCES)
+            .with_pressure(ElementMapping::new(&["007004"]).with_scale(0.01))
+            .with_temperature(ElementMapping::new(&["012101"]).with_offset(-273.15))
+            .with_dew_point(ElementMapping::new(&["012103"]).with_offset(-273.15))
+            .with_height(ElementMapping::new(&["007009", "010009"]))
+            .with_wind_direction(ElementMapping::new(&["011001"]))
+            .with_wind_speed(ElementMapping::new(&["011002"]))
+    }
+
+    /** Set the pressure mapping, converted to hPa. */
+    pub fn with_pressure(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { pressure: Some(mapping), ..self }
+    }
+
+    /** Set the temperature mapping, converted to °C. */
+    pub fn with_temperature(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { temperature: Some(mapping), ..self }
+    }
+
+    /** Set the dew point mapping, converted to °C. */
+    pub fn with_dew_point(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { dew_point: Some(mapping), ..self }
+    }
+
+    /** Set the geopotential height mapping, converted to meters. */
+    pub fn with_height(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { height: Some(mapping), ..self }
+    }
+
+    /** Set the wind direction mapping, in degrees. */
+    pub fn with_wind_direction(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { wind_direction: Some(mapping), ..self }
+    }
+
+    /** Set the wind speed mapping, converted to m/s. */
+    pub fn with_wind_speed(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { wind_speed: Some(mapping), ..self }
+    }
+
+    /** Set the vertical velocity mapping, converted to Pa/s. */
+    pub fn with_omega(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { omega: Some(mapping), ..self }
+    }
+
+    /** Set the cloud fraction mapping, converted to a fraction from 0 to 1. */
+    pub fn with_cloud_fraction(self, mapping: ElementMapping) -> Self {
+        ProfileMapping { cloud_fraction: Some(mapping), ..self }
+    }
+
+    /** Get the Table D sequences that hold a level. */
+    pub fn level_sequences(&self) -> &[String] {
+        &self.level_sequences
+    }
+
+    /** Build a sounding from a message using this mapping.
+     *
+     * The station and valid time are taken from the usual WMO sequences (301xxx) outside the
+     * levels. The levels are handled like the levels of a `RadiosondeTemplate`: a level flagged as
+     * the surface (008042 or 008001) fills in the surface values, levels with a higher pressure
+     * than the surface are dropped, and a dew point is derived from the humidity elements when
+     * none is mapped. The levels are then sorted by decreasing pressure, since other templates
+     * don't always report them from the bottom up.
+     */
+    pub fn extract(&self, bufr: &BufrMessage) -> Result<Sounding, Box<dyn Error>> {
+        let mut levels: Vec<&[Structure]> = vec![];
+        self.collect_levels(bufr.get_elements(), &mut levels);
+
+        if levels.is_empty() {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "No levels matching the profile mapping in message.",
+            )));
+        }
+
+        let mut profiles = Profiles::default();
+        profiles.reserve(levels.len());
+        for level in levels {
+            profiles.extract_data_row(self, level);
+        }
+
+        let mut surface = Surface::default();
+        profiles.take_surface_level(&mut surface);
+        if let Some(sfc_pres) = surface.pres.into_option() {
+            profiles.remove_below_surface(sfc_pres);
+        }
+        profiles.sort_by_pressure();
+
+        let mut station = StationInfo::new();
+        let mut identity = StationIdentity::default();
+        let mut time: Option<LaunchTime> = None;
+        identity.extract(bufr.get_elements());
+        find_station_and_time(bufr.get_elements(), &mut station, &mut time);
+        station = identity.apply(station);
+
+        let mut snd = Sounding::new().with_station_info(station);
+        if let Some(vt) = time.and_then(|t| t.to_datetime()) {
+            snd = snd.with_valid_time(vt);
+        }
+
+        snd = snd
+            .with_station_pressure(surface.pres)
+            .with_sfc_temperature(surface.temp)
+            .with_sfc_dew_point(surface.dewp)
+            .with_sfc_wind(surface.wind());
+
+        // Only set the profiles that were mapped, so the others stay empty. The pressure profile is
+        // always set since its length is the number of levels in the sounding. The dew point can
+        // also come from the humidity elements.
+        let wind = profiles.wind();
+        snd = snd.with_pressure_profile(profiles.pres);
+        if self.temperature.is_some() {
+            snd = snd.with_temperature_profile(profiles.temp);
+        }
+        if self.dew_point.is_some() || profiles.dewp.iter().any(|td| td.is_some()) {
+            snd = snd.with_dew_point_profile(profiles.dewp);
+        }
+        if self.height.is_some() {
+            snd = snd.with_height_profile(profiles.hgt);
+        }
+        if self.wind_direction.is_some() && self.wind_speed.is_some() {
+            snd = snd.with_wind_profile(wind);
+        }
+        if self.omega.is_some() {
+            snd = snd.with_pvv_profile(profiles.omega);
+        }
+        if self.cloud_fraction.is_some() {
+            snd = snd.with_cloud_fraction_profile(profiles.cloud);
+        }
+
+        Ok(snd)
+    }
+
+    /** The converted values of the mapped variables in a level. */
+    pub(super) fn level_values(&self, level: &[Structure]) -> LevelValues {
+        let value = |mapping: &Option<ElementMapping>| mapping.as_ref().and_then(|m| m.convert(level));
+
+        LevelValues {
+            pres: value(&self.pressure),
+            temp: value(&self.temperature),
+            dewp: value(&self.dew_point),
+            hgt: value(&self.height),
+            dir: value(&self.wind_direction),
+            spd: value(&self.wind_speed),
+            omega: value(&self.omega),
+            cloud: value(&self.cloud_fraction),
+        }
+    }
+
+    fn collect_levels<'a>(&self, items: &'a [Structure], levels: &mut Vec<&'a [Structure]>) {
+        for structure in items {
+            match structure {
+                Structure::Element(_) => {}
+                Structure::Group(grp) => self.collect_levels(grp.items(), levels),
+                Structure::Replication(rep) => {
+                    for block in rep.blocks() {
+                        match block {
+                            [Structure::Group(grp)] if self.level_sequences.iter().any(|c| c == grp.code()) => {
+                                levels.push(grp.items())
+                            }
+                            _ if self.level_sequences.is_empty() => levels.push(block),
+                            _ => self.collect_levels(block, levels),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/** Search the groups outside of any replication for the first station location and time. */
+fn find_station_and_time(items: &[Structure], station: &mut StationInfo, time: &mut Option<LaunchTime>) {
+    if station.location().is_none() {
+        *station = extract_station_location(items, station.clone());
+    }
+
+    if time.is_none() {
+        let mut t = LaunchTime::default();
+        extract_time_info(items, &mut t);
+        if t.to_datetime().is_some() {
+            *time = Some(t);
+        }
+    }
+
+    for structure in items {
+        if let Structure::Group(grp) = structure {
+            find_station_and_time(grp.items(), station, time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{easy_api::sounding_from_message, find_bufr_start, read_bufr_message_from_slice};
+
+    #[test]
+    fn test_radiosonde_mapping_matches_template() {
+        let bytes = include_bytes!("../../test-data/2017083115.bufr");
+        let start = find_bufr_start(bytes).unwrap();
+        let bufr = read_bufr_message_from_slice(&bytes[start..]).unwrap();
+
+        let expected = sounding_from_message(&bufr).unwrap();
+        let mapped = ProfileMapping::radiosonde().extract(&bufr).unwrap();
+
+        assert_eq!(mapped.pressure_profile().len(), 4878);
+        assert_eq!(mapped.pressure_profile(), expected.pressure_profile());
+        assert_eq!(mapped.temperature_profile(), expected.temperature_profile());
+        assert_eq!(mapped.dew_point_profile(), expected.dew_point_profile());
+        assert_eq!(mapped.height_profile(), expected.height_profile());
+        assert_eq!(mapped.wind_profile(), expected.wind_profile());
+
+        assert_eq!(mapped.station_pressure(), expected.station_pressure());
+        assert_eq!(mapped.sfc_temperature(), expected.sfc_temperature());
+        assert_eq!(mapped.sfc_dew_point(), expected.sfc_dew_point());
+        assert_eq!(mapped.sfc_wind(), expected.sfc_wind());
+        assert_eq!(mapped.valid_time(), expected.valid_time());
+        assert_eq!(mapped.station_info(), expected.station_info());
+    }
+
+    #[test]
+    fn test_element_mapping_convert() {
+        let level = [Structure::Element(crate::types::Element::new(
+            crate::types::Value::Float(9310.0),
+            "Pa",
+            "PRESSURE",
+            "007004",
+        ))];
+
+        let pres = ElementMapping::new(&["007004"]).with_scale(0.01);
+        assert_eq!(pres.convert(&level), Some(93.1));
+
+        let missing = ElementMapping::new(&["010004"]);
+        assert_eq!(missing.convert(&level), None);
+    }
+}
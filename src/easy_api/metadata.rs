@@ -0,0 +1,192 @@
+use std::fmt::Display;
+
+use metfor::Meters;
+
+use crate::{
+    tables::code_flag::CODE_FLAG,
+    types::{Element, Structure},
+};
+
+/** A code table value along with its meaning from the WMO code tables.
+ *
+ * The radiosonde type (002011) and tracking technique (002014) use the WMO Common Code Tables C-2
+ * and C-7. Only a partial copy of those is included, the "no radiosonde" entries, a few current
+ * Vaisala and Graw systems, and the main tracking techniques, so most radiosonde types have no
+ * meaning available. The code itself is always kept.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CodeValue {
+    fxy: &'static str,
+    code: u64,
+}
+
+impl CodeValue {
+    pub(super) fn from_element(el: &Element) -> Option<Self> {
+        el.get_code_val().map(|code| CodeValue { fxy: el.code(), code })
+    }
+
+    /** The raw value from the code table. */
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    /** The meaning of the value, if it is in the code tables. */
+    pub fn meaning(&self) -> Option<&'static str> {
+        CODE_FLAG.get(&(self.fxy, self.code)).copied()
+    }
+}
+
+impl Display for CodeValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self.meaning() {
+            Some(meaning) => write!(f, "{} ({})", self.code, meaning),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
+
+/** Information about the radiosonde, ground system, and launch. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RadiosondeMetadata {
+    radiosonde_type: Option<CodeValue>,
+    solar_ir_correction: Option<CodeValue>,
+    tracking_technique: Option<CodeValue>,
+    measuring_equipment: Option<CodeValue>,
+    humidity_correction: Option<CodeValue>,
+    pressure_sensor: Option<CodeValue>,
+    temperature_sensor: Option<CodeValue>,
+    humidity_sensor: Option<CodeValue>,
+    ground_system: Option<CodeValue>,
+    balloon_type: Option<CodeValue>,
+    geopotential_height_calculation: Option<CodeValue>,
+    termination_reason: Option<CodeValue>,
+    software_version: Option<String>,
+    serial_number: Option<String>,
+    ascension_number: Option<u32>,
+    operating_frequency: Option<f64>,
+    launch_height: Option<Meters>,
+}
+
+impl RadiosondeMetadata {
+    /** Radiosonde type (002011). */
+    pub fn radiosonde_type(&self) -> Option<CodeValue> {
+        self.radiosonde_type
+    }
+
+    /** Solar and infrared radiation correction (002013). */
+    pub fn solar_ir_correction(&self) -> Option<CodeValue> {
+        self.solar_ir_correction
+    }
+
+    /** Tracking technique/status of system used (002014). */
+    pub fn tracking_technique(&self) -> Option<CodeValue> {
+        self.tracking_technique
+    }
+
+    /** Type of measuring equipment used (002003). */
+    pub fn measuring_equipment(&self) -> Option<CodeValue> {
+        self.measuring_equipment
+    }
+
+    /** Correction algorithms for humidity measurements (002017). */
+    pub fn humidity_correction(&self) -> Option<CodeValue> {
+        self.humidity_correction
+    }
+
+    /** Type of pressure sensor (002095). */
+    pub fn pressure_sensor(&self) -> Option<CodeValue> {
+        self.pressure_sensor
+    }
+
+    /** Type of temperature sensor (002096). */
+    pub fn temperature_sensor(&self) -> Option<CodeValue> {
+        self.temperature_sensor
+    }
+
+    /** Type of humidity sensor (002097). */
+    pub fn humidity_sensor(&self) -> Option<CodeValue> {
+        self.humidity_sensor
+    }
+
+    /** Radiosonde ground receiving system (002066). */
+    pub fn ground_system(&self) -> Option<CodeValue> {
+        self.ground_system
+    }
+
+    /** Type of balloon (002081). */
+    pub fn balloon_type(&self) -> Option<CodeValue> {
+        self.balloon_type
+    }
+
+    /** Geopotential height calculation (002191). */
+    pub fn geopotential_height_calculation(&self) -> Option<CodeValue> {
+        self.geopotential_height_calculation
+    }
+
+    /** Reason for termination (035035). */
+    pub fn termination_reason(&self) -> Option<CodeValue> {
+        self.termination_reason
+    }
+
+    /** Software identification and version number (025061). */
+    pub fn software_version(&self) -> Option<&str> {
+        self.software_version.as_deref()
+    }
+
+    /** Radiosonde serial number (001081). */
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    /** Radiosonde ascension number (001082). */
+    pub fn ascension_number(&self) -> Option<u32> {
+        self.ascension_number
+    }
+
+    /** Radiosonde operating frequency in Hz (002067). */
+    pub fn operating_frequency(&self) -> Option<f64> {
+        self.operating_frequency
+    }
+
+    /** Height of the launch site (007007). */
+    pub fn launch_height(&self) -> Option<Meters> {
+        self.launch_height
+    }
+
+    /** Fill in the metadata from elements in these structures and any nested groups. Replications
+     * hold the levels, so they are skipped.
+     */
+    pub(super) fn extract<'a>(&mut self, items: impl IntoIterator<Item = &'a Structure>) {
+        for structure in items {
+            let el = match structure {
+                Structure::Element(el) => el,
+                Structure::Group(grp) => {
+                    self.extract(grp.items());
+                    continue;
+                }
+                Structure::Replication(_) => continue,
+            };
+
+            match el.code() {
+                "002011" => self.radiosonde_type = CodeValue::from_element(el),
+                "002013" => self.solar_ir_correction = CodeValue::from_element(el),
+                "002014" => self.tracking_technique = CodeValue::from_element(el),
+                "002003" => self.measuring_equipment = CodeValue::from_element(el),
+                "002017" => self.humidity_correction = CodeValue::from_element(el),
+                "002095" => self.pressure_sensor = CodeValue::from_element(el),
+                "002096" => self.temperature_sensor = CodeValue::from_element(el),
+                "002097" => self.humidity_sensor = CodeValue::from_element(el),
+                "002066" => self.ground_system = CodeValue::from_element(el),
+                "002081" => self.balloon_type = CodeValue::from_element(el),
+                "002191" => self.geopotential_height_calculation = CodeValue::from_element(el),
+                "035035" => self.termination_reason = CodeValue::from_element(el),
+                "025061" => self.software_version = el.get_str_val().map(String::from),
+                "001081" => self.serial_number = el.get_str_val().map(String::from),
+                "001082" => self.ascension_number = el.get_u32_val(),
+                "002067" => self.operating_frequency = el.get_f64_val(),
+                "007007" => self.launch_height = el.get_f64_val().map(Meters),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use metfor::{
+    Celsius, HectoPascal, dew_point_from_p_and_mw, dew_point_from_p_and_specific_humidity,
+    dew_point_from_vapor_pressure_water, vapor_pressure_water,
+};
+use optional::Optioned;
+
+/** Which moisture variable a dew point came from. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MoistureSource {
+    /** Reported directly (012103). */
+    DewPoint,
+    /** Derived from the relative humidity (013009 or 013003) and temperature. */
+    RelativeHumidity,
+    /** Derived from the mixing ratio (013002) and pressure. */
+    MixingRatio,
+    /** Derived from the specific humidity (013001) and pressure. */
+    SpecificHumidity,
+}
+
+/** The moisture variables reported for a level, in the units they're reported in. */
+#[derive(Default)]
+pub(super) struct Moisture {
+    pub(super) dew_point: Option<Celsius>,
+    /** Percent */
+    pub(super) relative_humidity: Option<f64>,
+    /** kg/kg */
+    pub(super) mixing_ratio: Option<f64>,
+    /** kg/kg */
+    pub(super) specific_humidity: Option<f64>,
+}
+
+impl Moisture {
+    /** Use the reported dew point if there is one, otherwise derive it from the first of relative
+     * humidity, mixing ratio, or specific humidity that can be converted.
+     */
+    pub(super) fn dew_point(
+        &self,
+        temperature: Optioned<Celsius>,
+        pressure: Optioned<HectoPascal>,
+    ) -> Option<(Celsius, MoistureSource)> {
+        let temperature = temperature.into_option();
+        let pressure = pressure.into_option();
+
+        // A dew point can't be computed for perfectly dry air, and the metfor conversions don't
+        // guard against it.
+        let positive = |x: &f64| *x > 0.0;
+
+        let from_rh = || {
+            let rh = self.relative_humidity.filter(positive)?;
+            let vp = vapor_pressure_water(temperature?)?;
+            dew_point_from_vapor_pressure_water(HectoPascal(vp.0 * rh / 100.0))
+        };
+        let from_mw = || dew_point_from_p_and_mw(pressure?, self.mixing_ratio.filter(positive)?);
+        let from_q = || dew_point_from_p_and_specific_humidity(pressure?, self.specific_humidity.filter(positive)?);
+
+        self.dew_point
+            .map(|td| (td, MoistureSource::DewPoint))
+            .or_else(|| from_rh().map(|td| (td, MoistureSource::RelativeHumidity)))
+            .or_else(|| from_mw().map(|td| (td, MoistureSource::MixingRatio)))
+            .or_else(|| from_q().map(|td| (td, MoistureSource::SpecificHumidity)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dew_point() {
+        let t: Optioned<Celsius> = Celsius(20.0).into();
+        let p: Optioned<HectoPascal> = HectoPascal(1000.0).into();
+        let source = |moisture: &Moisture, t, p| moisture.dew_point(t, p).map(|(_, source)| source);
+
+        let moisture = Moisture {
+            dew_point: Some(Celsius(10.0)),
+            relative_humidity: Some(50.0),
+            mixing_ratio: Some(0.01),
+            specific_humidity: Some(0.01),
+        };
+        assert_eq!(moisture.dew_point(t, p), Some((Celsius(10.0), MoistureSource::DewPoint)));
+
+        let moisture = Moisture {
+            dew_point: None,
+            ..moisture
+        };
+        assert_eq!(source(&moisture, t, p), Some(MoistureSource::RelativeHumidity));
+        let (td, _) = moisture.dew_point(t, p).unwrap();
+        assert!(td.0 > 9.0 && td.0 < 10.0);
+
+        // The relative humidity needs the temperature, the others need the pressure.
+        assert_eq!(source(&moisture, Optioned::default(), p), Some(MoistureSource::MixingRatio));
+        let (td, _) = moisture.dew_point(Optioned::default(), p).unwrap();
+        assert!(td.0 > 13.0 && td.0 < 15.0);
+        assert_eq!(moisture.dew_point(Optioned::default(), Optioned::default()), None);
+
+        // Perfectly dry air has no dew point, so the next variable is used.
+        let dry = Moisture {
+            relative_humidity: Some(0.0),
+            mixing_ratio: Some(0.0),
+            ..moisture
+        };
+        assert_eq!(source(&dry, t, p), Some(MoistureSource::SpecificHumidity));
+
+        // Saturated air is at its dew point.
+        let saturated = Moisture {
+            relative_humidity: Some(100.0),
+            ..Moisture::default()
+        };
+        assert!(saturated.dew_point(t, p).is_some_and(|(td, _)| (td.0 - 20.0).abs() < 0.1));
+
+        assert_eq!(Moisture::default().dew_point(t, p), None);
+    }
+}
@@ -0,0 +1,449 @@
+use std::error::Error;
+
+use metfor::{Celsius, HectoPascal, Kelvin, Meters};
+use optional::Optioned;
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::{CodeValue, LaunchTime, Moisture, extract_station_location, extract_time_info};
+use crate::types::{BufrMessage, Element, Structure};
+
+/** Satellite radio occultation template. */
+pub(super) const OCCULTATION_TEMPLATE: &str = "310026";
+
+/** WGS 84 semi-major axis, flattening, and gravity ratio for converting geopotential height to
+ * geometric height.
+ */
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+const WGS84_M: f64 = 0.003_449_786_003_08;
+const STANDARD_GRAVITY: f64 = 9.806_65;
+
+/** A single level of a radio occultation profile. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OccultationLevel {
+    pub(super) geometric_height: Option<Meters>,
+    pub(super) geopotential_height: Option<Meters>,
+    pub(super) pressure: Option<HectoPascal>,
+    pub(super) temperature: Option<Celsius>,
+    pub(super) specific_humidity: Option<f64>,
+    pub(super) tangent_point: Option<(f64, f64)>,
+}
+
+impl OccultationLevel {
+    /** Height above the geoid, computed from the geopotential height. */
+    pub fn geometric_height(&self) -> Option<Meters> {
+        self.geometric_height
+    }
+
+    /** Geopotential height (007009). */
+    pub fn geopotential_height(&self) -> Option<Meters> {
+        self.geopotential_height
+    }
+
+    /** Pressure (010004). */
+    pub fn pressure(&self) -> Option<HectoPascal> {
+        self.pressure
+    }
+
+    /** Temperature (012001). */
+    pub fn temperature(&self) -> Option<Celsius> {
+        self.temperature
+    }
+
+    /** Specific humidity in kg/kg (013001). */
+    pub fn specific_humidity(&self) -> Option<f64> {
+        self.specific_humidity
+    }
+
+    /** Latitude and longitude of the tangent point at this level.
+     *
+     * The tangent points are reported with the bending angles, so they are interpolated to the
+     * impact parameter of the level, computed from its height and refractivity.
+     */
+    pub fn tangent_point(&self) -> Option<(f64, f64)> {
+        self.tangent_point
+    }
+}
+
+/** A temperature and humidity profile retrieved from a GNSS radio occultation (310026). */
+#[derive(Clone, Debug)]
+pub struct OccultationProfile {
+    pub(super) satellite: Option<CodeValue>,
+    pub(super) transmitter: Option<u32>,
+    pub(super) quality_flags: Option<CodeValue>,
+    pub(super) confidence: Option<f64>,
+    pub(super) sounding: Sounding,
+    pub(super) levels: Vec<OccultationLevel>,
+}
+
+impl OccultationProfile {
+    /** The receiving (low earth orbit) satellite (001007). */
+    pub fn satellite(&self) -> Option<CodeValue> {
+        self.satellite
+    }
+
+    /** The transmitting GNSS satellite (001050). */
+    pub fn transmitter(&self) -> Option<u32> {
+        self.transmitter
+    }
+
+    /** Quality flags for radio occultation data (033039). */
+    pub fn quality_flags(&self) -> Option<CodeValue> {
+        self.quality_flags
+    }
+
+    /** Per cent confidence in the whole profile (033007). */
+    pub fn confidence(&self) -> Option<f64> {
+        self.confidence
+    }
+
+    /** A sounding at the occultation point, the height profile is geopotential height. */
+    pub fn sounding(&self) -> &Sounding {
+        &self.sounding
+    }
+
+    /** Throw away everything but the sounding. */
+    pub fn into_sounding(self) -> Sounding {
+        self.sounding
+    }
+
+    /** Get the levels, these line up with the sounding profiles. The first is the surface. */
+    pub fn levels(&self) -> &[OccultationLevel] {
+        &self.levels
+    }
+}
+
+/** Get the radio occultation profiles (310026) from every subset of a message.
+ *
+ * Only the retrieved temperature and humidity levels become levels of the sounding, the bending
+ * angles and refractivity are used to place the tangent points.
+ */
+pub fn occultation_profiles(bufr: &BufrMessage) -> Result<Vec<OccultationProfile>, Box<dyn Error>> {
+    let mut profiles = vec![];
+
+    for subset in bufr.subsets() {
+        for structure in subset {
+            if let Structure::Group(grp) = structure
+                && grp.code() == OCCULTATION_TEMPLATE
+            {
+                profiles.push(profile_from_template(grp.items()));
+            }
+        }
+    }
+
+    if profiles.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No radio occultation template in message.",
+        )));
+    }
+
+    Ok(profiles)
+}
+
+/** A bending angle sample, only the impact parameter and tangent point are needed. */
+struct BendingAngle {
+    impact_parameter: f64,
+    lat: f64,
+    lon: f64,
+}
+
+fn profile_from_template(items: &[Structure]) -> OccultationProfile {
+    let mut time = LaunchTime::default();
+    extract_time_info(items, &mut time);
+    let location = extract_station_location(items, StationInfo::new()).location();
+    let latitude = location.map_or(45.0, |(lat, _)| lat);
+
+    let mut profile = OccultationProfile {
+        satellite: None,
+        transmitter: None,
+        quality_flags: None,
+        confidence: None,
+        sounding: Sounding::new(),
+        levels: vec![],
+    };
+
+    let mut surface = OccultationLevel::default();
+    let mut radius_of_curvature: Option<f64> = None;
+    let mut geoid_undulation = 0.0;
+    let mut bending: Vec<BendingAngle> = vec![];
+
+    for structure in items {
+        match structure {
+            Structure::Element(el) => match el.code() {
+                "001050" if profile.transmitter.is_none() => profile.transmitter = el.get_u32_val(),
+                "033039" if profile.quality_flags.is_none() => profile.quality_flags = CodeValue::from_element(el),
+                "033007" if profile.confidence.is_none() => profile.confidence = el.get_f64_val(),
+                "010035" => radius_of_curvature = el.get_f64_val(),
+                "010036" => geoid_undulation = el.get_f64_val().unwrap_or(0.0),
+                // The surface values come after the replications, the values with changed widths
+                // are their errors.
+                "007009" if surface.geopotential_height.is_none() => surface.geopotential_height = height(el),
+                "010004" if surface.pressure.is_none() => surface.pressure = pressure(el),
+                _ => {}
+            },
+            Structure::Replication(rep) => {
+                for block in rep.blocks() {
+                    if let Some(sample) = bending_angle(block) {
+                        bending.push(sample);
+                    } else if has_element(block, "012001") {
+                        profile.levels.push(extract_level(block));
+                    }
+                }
+            }
+            // Satellite identifier, instrument and product type
+            Structure::Group(grp) if grp.code() == "310022" => {
+                for structure in grp.items() {
+                    if let Structure::Element(el) = structure
+                        && el.code() == "001007"
+                    {
+                        profile.satellite = CodeValue::from_element(el);
+                    }
+                }
+            }
+            Structure::Group(_) => {}
+        }
+    }
+
+    // Retrievals are usually reported from the bottom up, but put them in order if they aren't.
+    let first = profile.levels.first().and_then(|lvl| lvl.pressure);
+    let last = profile.levels.last().and_then(|lvl| lvl.pressure);
+    if first.zip(last).is_some_and(|(first, last)| first < last) {
+        profile.levels.reverse();
+    }
+
+    bending.sort_by(|a, b| a.impact_parameter.total_cmp(&b.impact_parameter));
+    surface.geometric_height = surface.geopotential_height.map(|z| geometric_height(z, latitude));
+    for level in profile.levels.iter_mut() {
+        level.geometric_height = level.geopotential_height.map(|z| geometric_height(z, latitude));
+        level.tangent_point = radius_of_curvature
+            .and_then(|r| impact_parameter(level, r + geoid_undulation))
+            .and_then(|a| tangent_point(&bending, a));
+    }
+
+    let mut station = StationInfo::new();
+    if let Some(location) = location {
+        station = station.with_lat_lon(location);
+    }
+    if let Some(elevation) = surface.geopotential_height {
+        station = station.with_elevation(elevation);
+    }
+
+    let mut snd = Sounding::new().with_station_info(station);
+    if let Some(vt) = time.to_datetime() {
+        snd = snd.with_valid_time(vt);
+    }
+
+    let levels = &profile.levels;
+    let dewp = levels
+        .iter()
+        .map(|lvl| {
+            Moisture {
+                specific_humidity: lvl.specific_humidity,
+                ..Moisture::default()
+            }
+            .dew_point(lvl.temperature.into(), lvl.pressure.into())
+            .map(|(td, _)| td)
+            .into()
+        })
+        .collect();
+
+    profile.sounding = snd
+        .with_station_pressure(Optioned::<HectoPascal>::from(surface.pressure))
+        .with_pressure_profile(levels.iter().map(|lvl| lvl.pressure.into()).collect())
+        .with_temperature_profile(levels.iter().map(|lvl| lvl.temperature.into()).collect())
+        .with_dew_point_profile(dewp)
+        .with_height_profile(levels.iter().map(|lvl| lvl.geopotential_height.into()).collect());
+
+    profile.levels.insert(0, surface);
+
+    profile
+}
+
+fn height(el: &Element) -> Option<Meters> {
+    el.get_f64_val().map(Meters)
+}
+
+fn pressure(el: &Element) -> Option<HectoPascal> {
+    el.get_f64_val().map(|p| HectoPascal(p / 100.0))
+}
+
+fn has_element(block: &[Structure], code: &str) -> bool {
+    block.iter().any(|s| matches!(s, Structure::Element(el) if el.code() == code))
+}
+
+/** Read a retrieved level, the first value of each element is the retrieval and the second, with
+ * a changed width, is its error.
+ */
+fn extract_level(block: &[Structure]) -> OccultationLevel {
+    let mut level = OccultationLevel::default();
+
+    for structure in block {
+        if let Structure::Element(el) = structure {
+            match el.code() {
+                "007009" if level.geopotential_height.is_none() => level.geopotential_height = height(el),
+                "010004" if level.pressure.is_none() => level.pressure = pressure(el),
+                "012001" if level.temperature.is_none() => {
+                    level.temperature = el.get_f64_val().map(Kelvin).map(Celsius::from)
+                }
+                "013001" if level.specific_humidity.is_none() => level.specific_humidity = el.get_f64_val(),
+                _ => {}
+            }
+        }
+    }
+
+    level
+}
+
+/** A bending angle block has the tangent point (301021) and the impact parameter (007040) for
+ * each frequency, use the corrected (zero frequency) one if it's there.
+ */
+fn bending_angle(block: &[Structure]) -> Option<BendingAngle> {
+    let (lat, lon) = extract_station_location(block, StationInfo::new()).location()?;
+
+    let mut impact_parameter = None;
+    for structure in block {
+        if let Structure::Replication(rep) = structure {
+            for freq in rep.blocks() {
+                let mut frequency = None;
+                let mut a = None;
+                for structure in freq {
+                    match structure {
+                        Structure::Element(el) if el.code() == "002121" => frequency = el.get_f64_val(),
+                        Structure::Element(el) if el.code() == "007040" => a = el.get_f64_val(),
+                        _ => {}
+                    }
+                }
+
+                if a.is_some() && (impact_parameter.is_none() || frequency == Some(0.0)) {
+                    impact_parameter = a;
+                }
+            }
+        }
+    }
+
+    Some(BendingAngle {
+        impact_parameter: impact_parameter?,
+        lat,
+        lon,
+    })
+}
+
+/** The impact parameter `a = n r` of a level, where `r` is the distance from the center of
+ * curvature and `n` the refractive index. Without a temperature the refractivity is ignored.
+ */
+fn impact_parameter(level: &OccultationLevel, radius: f64) -> Option<f64> {
+    let z = level.geometric_height?.0;
+
+    let refractivity = match (level.pressure, level.temperature) {
+        (Some(HectoPascal(p)), Some(t)) => {
+            let Kelvin(t) = Kelvin::from(t);
+            let q = level.specific_humidity.unwrap_or(0.0);
+            let e = q * p / (0.622 + 0.378 * q);
+            77.6 * p / t + 3.73e5 * e / (t * t)
+        }
+        _ => 0.0,
+    };
+
+    Some((1.0 + refractivity * 1.0e-6) * (radius + z))
+}
+
+/** Interpolate the tangent point to an impact parameter, `None` outside of the bending angles. */
+fn tangent_point(bending: &[BendingAngle], a: f64) -> Option<(f64, f64)> {
+    let i = bending.partition_point(|b| b.impact_parameter < a);
+    let above = bending.get(i)?;
+    if above.impact_parameter == a {
+        return Some((above.lat, above.lon));
+    }
+    let below = bending.get(i.checked_sub(1)?)?;
+
+    let w = (a - below.impact_parameter) / (above.impact_parameter - below.impact_parameter);
+    // Take the short way around at the date line.
+    let mut dlon = above.lon - below.lon;
+    if dlon > 180.0 {
+        dlon -= 360.0;
+    } else if dlon < -180.0 {
+        dlon += 360.0;
+    }
+    let mut lon = below.lon + w * dlon;
+    if lon > 180.0 {
+        lon -= 360.0;
+    } else if lon < -180.0 {
+        lon += 360.0;
+    }
+
+    Some((below.lat + w * (above.lat - below.lat), lon))
+}
+
+/** Convert geopotential height to height above the geoid with the normal gravity and effective
+ * radius of the WGS 84 ellipsoid at a latitude.
+ */
+fn geometric_height(geopotential_height: Meters, latitude: f64) -> Meters {
+    let sin2 = latitude.to_radians().sin().powi(2);
+    let sin2_2 = (2.0 * latitude.to_radians()).sin().powi(2);
+
+    let gravity = 9.780_325_335_9 * (1.0 + 5.302_4e-3 * sin2 - 5.8e-6 * sin2_2);
+    let radius = WGS84_A / (1.0 + WGS84_F + WGS84_M - 2.0 * WGS84_F * sin2);
+
+    let h = geopotential_height.0;
+    Meters(radius * h / (gravity / STANDARD_GRAVITY * radius - h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Replication, Value};
+
+    fn float(fxy: &'static str, val: f64) -> Structure {
+        Structure::Element(Element::new(Value::Float(val), "", "", fxy))
+    }
+
+    #[test]
+    fn test_geometric_height() {
+        assert_eq!(geometric_height(Meters(0.0), 45.0), Meters(0.0));
+
+        // Gravity is weaker at the equator, so the same geopotential height is higher up.
+        for (latitude, expected) in [(0.0, 10_042.81), (45.0, 10_016.22), (90.0, 9_989.65)] {
+            let Meters(z) = geometric_height(Meters(10_000.0), latitude);
+            assert!((z - expected).abs() < 0.01, "{} at {}", z, latitude);
+        }
+    }
+
+    #[test]
+    fn test_profile_from_template() {
+        // The levels from the top down, then the surface.
+        let mut rep = Replication::new_with_capacity(8, 4);
+        for (z, p, t) in [(10_000.0, 26_500.0, 223.15), (1_000.0, 90_000.0, 283.15)] {
+            rep.push(float("007009", z));
+            rep.push(float("010004", p));
+            rep.push(float("012001", t));
+            rep.push(float("013001", 0.001));
+        }
+        let items = vec![
+            float("005001", 45.0),
+            float("006001", -100.0),
+            Structure::Replication(rep),
+            float("007009", 100.0),
+            float("010004", 100_000.0),
+        ];
+
+        let profile = profile_from_template(&items);
+        let levels = profile.levels();
+        assert_eq!(levels.len(), 3);
+
+        assert_eq!(levels[0].pressure(), Some(HectoPascal(1000.0)));
+        assert_eq!(levels[1].pressure(), Some(HectoPascal(900.0)));
+        assert_eq!(levels[2].geopotential_height(), Some(Meters(10_000.0)));
+        assert!(levels[2].geometric_height().is_some_and(|z| (z.0 - 10_016.22).abs() < 0.01));
+        assert!(levels[0].geometric_height().is_some_and(|z| z > Meters(100.0)));
+        // There are no bending angles to place the tangent points.
+        assert_eq!(levels[1].tangent_point(), None);
+
+        // The sounding has the geopotential heights.
+        let snd = profile.sounding();
+        assert_eq!(snd.station_info().elevation().into_option(), Some(Meters(100.0)));
+        assert_eq!(snd.height_profile()[2].into_option(), Some(Meters(10_000.0)));
+        assert_eq!(snd.pressure_profile()[0].into_option(), Some(HectoPascal(1000.0)));
+        assert!(snd.dew_point_profile()[1].is_some());
+    }
+}
@@ -0,0 +1,267 @@
+use std::error::Error;
+
+use chrono::NaiveDateTime;
+use metfor::{Celsius, HectoPascal, Kelvin, Meters};
+use sounding_analysis::StationInfo;
+
+use super::{CodeValue, LaunchTime, extract_station_location, extract_time_info};
+use crate::types::{BufrMessage, Element, Structure};
+
+/** Subsurface profile templates, profiling floats (315003) and XBTs (315004). */
+pub(super) const OCEAN_TEMPLATES: [&str; 2] = ["315003", "315004"];
+
+/** A single level of an ocean profile. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OceanLevel {
+    pub(super) depth: Option<Meters>,
+    pub(super) derived_depth: bool,
+    pub(super) pressure: Option<HectoPascal>,
+    pub(super) temperature: Option<Celsius>,
+    pub(super) salinity: Option<f64>,
+    pub(super) depth_quality: Option<CodeValue>,
+    pub(super) temperature_quality: Option<CodeValue>,
+    pub(super) salinity_quality: Option<CodeValue>,
+}
+
+impl OceanLevel {
+    /** Depth below the sea surface (007062 or 007063), or computed from the water pressure. */
+    pub fn depth(&self) -> Option<Meters> {
+        self.depth
+    }
+
+    /** Whether the depth was computed from the water pressure instead of reported. */
+    pub fn depth_is_derived(&self) -> bool {
+        self.derived_depth
+    }
+
+    /** Water pressure (007065), the pressure due to the water above the level. */
+    pub fn pressure(&self) -> Option<HectoPascal> {
+        self.pressure
+    }
+
+    /** Sea water temperature (022043 or 022045). */
+    pub fn temperature(&self) -> Option<Celsius> {
+        self.temperature
+    }
+
+    /** Salinity in parts per thousand (022062 or 022064). */
+    pub fn salinity(&self) -> Option<f64> {
+        self.salinity
+    }
+
+    /** GTSPP quality flag (033050) for the depth or pressure. */
+    pub fn depth_quality(&self) -> Option<CodeValue> {
+        self.depth_quality
+    }
+
+    /** GTSPP quality flag (033050) for the temperature. */
+    pub fn temperature_quality(&self) -> Option<CodeValue> {
+        self.temperature_quality
+    }
+
+    /** GTSPP quality flag (033050) for the salinity. */
+    pub fn salinity_quality(&self) -> Option<CodeValue> {
+        self.salinity_quality
+    }
+}
+
+/** A subsurface temperature and salinity profile from a float, buoy, or XBT. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct OceanProfile {
+    pub(super) template: &'static str,
+    pub(super) platform_id: Option<u32>,
+    pub(super) ship_id: Option<String>,
+    pub(super) profile_id: Option<String>,
+    pub(super) instrument: Option<CodeValue>,
+    pub(super) time: Option<NaiveDateTime>,
+    pub(super) location: Option<(f64, f64)>,
+    pub(super) levels: Vec<OceanLevel>,
+}
+
+impl OceanProfile {
+    /** The template the profile came from, e.g. "315003". */
+    pub fn template(&self) -> &'static str {
+        self.template
+    }
+
+    /** WMO marine observing platform extended identifier (001087). */
+    pub fn platform_id(&self) -> Option<u32> {
+        self.platform_id
+    }
+
+    /** Ship or mobile land station identifier (001011). */
+    pub fn ship_id(&self) -> Option<&str> {
+        self.ship_id.as_deref()
+    }
+
+    /** Unique identifier for the profile (001079). */
+    pub fn profile_id(&self) -> Option<&str> {
+        self.profile_id.as_deref()
+    }
+
+    /** Instrument type for the water temperature and salinity profile (022067). */
+    pub fn instrument(&self) -> Option<CodeValue> {
+        self.instrument
+    }
+
+    /** Time of the profile. */
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        self.time
+    }
+
+    /** Latitude and longitude of the profile. */
+    pub fn location(&self) -> Option<(f64, f64)> {
+        self.location
+    }
+
+    /** The levels in the order they were reported, usually increasing depth. */
+    pub fn levels(&self) -> &[OceanLevel] {
+        &self.levels
+    }
+}
+
+/** Get the subsurface ocean profiles (315003, 315004) from every subset of a message.
+ *
+ * Messages using the oceanographic master table (10) are decoded with the meteorological tables,
+ * which have the same entries for these sequences. As with any other message, reading it fails if
+ * it has descriptors that aren't in those tables.
+ */
+pub fn ocean_profiles(bufr: &BufrMessage) -> Result<Vec<OceanProfile>, Box<dyn Error>> {
+    let mut profiles = vec![];
+
+    for subset in bufr.subsets() {
+        for structure in subset {
+            if let Structure::Group(grp) = structure
+                && let Some(template) = OCEAN_TEMPLATES.iter().find(|&&t| t == grp.code())
+            {
+                profiles.push(profile_from_template(template, grp.items()));
+            }
+        }
+    }
+
+    if profiles.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No supported ocean profile template in message.",
+        )));
+    }
+
+    Ok(profiles)
+}
+
+fn profile_from_template(template: &'static str, items: &[Structure]) -> OceanProfile {
+    let mut time = LaunchTime::default();
+    extract_time_info(items, &mut time);
+    let location = extract_station_location(items, StationInfo::new()).location();
+
+    let mut profile = OceanProfile {
+        template,
+        platform_id: None,
+        ship_id: None,
+        profile_id: None,
+        instrument: None,
+        time: time.to_datetime(),
+        location,
+        levels: vec![],
+    };
+
+    for structure in items {
+        match structure {
+            Structure::Element(el) => match el.code() {
+                "001087" => profile.platform_id = el.get_u32_val(),
+                "001011" => profile.ship_id = text(el),
+                "001079" => profile.profile_id = text(el),
+                "022067" => profile.instrument = CodeValue::from_element(el),
+                _ => {}
+            },
+            _ => collect_levels(std::slice::from_ref(structure), &mut profile.levels),
+        }
+    }
+
+    let latitude = location.map_or(45.0, |(lat, _)| lat);
+    for level in profile.levels.iter_mut() {
+        if level.depth.is_none()
+            && let Some(pressure) = level.pressure
+        {
+            level.depth = Some(depth_from_pressure(pressure, latitude));
+            level.derived_depth = true;
+        }
+    }
+
+    profile
+}
+
+fn text(el: &Element) -> Option<String> {
+    el.get_str_val().map(str::trim).filter(|s| !s.is_empty()).map(String::from)
+}
+
+/** Every repetition of a replication with a depth or water pressure is a level. */
+fn collect_levels(items: &[Structure], levels: &mut Vec<OceanLevel>) {
+    for structure in items {
+        match structure {
+            Structure::Element(_) => {}
+            Structure::Group(grp) => collect_levels(grp.items(), levels),
+            Structure::Replication(rep) => {
+                for block in rep.blocks() {
+                    let is_level = block.iter().any(|s| {
+                        matches!(s, Structure::Element(el) if ["007062", "007063", "007065"].contains(&el.code()))
+                    });
+
+                    if is_level {
+                        levels.push(extract_level(block));
+                    } else {
+                        collect_levels(block, levels);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/** Read a single level, each value is followed by a qualifier (008080) and its quality flag
+ * (033050).
+ */
+fn extract_level(block: &[Structure]) -> OceanLevel {
+    let mut level = OceanLevel::default();
+    let mut last: Option<&str> = None;
+
+    for structure in block {
+        let el = match structure {
+            Structure::Element(el) => el,
+            _ => continue,
+        };
+
+        match el.code() {
+            "007062" | "007063" => level.depth = el.get_f64_val().map(Meters),
+            "007065" => level.pressure = el.get_f64_val().map(|p| HectoPascal(p / 100.0)),
+            "022043" | "022045" => level.temperature = el.get_f64_val().map(Kelvin).map(Celsius::from),
+            "022062" | "022064" => level.salinity = el.get_f64_val(),
+            "033050" => {
+                let quality = CodeValue::from_element(el);
+                match last {
+                    Some("007062" | "007063" | "007065") => level.depth_quality = quality,
+                    Some("022043" | "022045") => level.temperature_quality = quality,
+                    Some("022062" | "022064") => level.salinity_quality = quality,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        if el.code() != "008080" && el.code() != "033050" {
+            last = Some(el.code());
+        }
+    }
+
+    level
+}
+
+/** Depth from the water pressure and latitude (Saunders, 1981), good to about 0.1% of depth. */
+fn depth_from_pressure(pressure: HectoPascal, latitude: f64) -> Meters {
+    // In decibars
+    let p = pressure.0 / 100.0;
+    let sin_lat = latitude.to_radians().sin();
+    let c1 = (5.92 + 5.25 * sin_lat * sin_lat) * 1.0e-3;
+
+    Meters((1.0 - c1) * p - 2.21e-6 * p * p)
+}
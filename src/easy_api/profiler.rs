@@ -0,0 +1,322 @@
+use std::error::Error;
+
+use metfor::{Celsius, HectoPascal, Kelvin, Knots, Meters, MetersPSec, WindSpdDir, WindUV};
+use optional::{Optioned, none};
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::{CodeValue, LaunchTime, StationIdentity, extract_station_location, extract_time_info};
+use crate::types::{BufrMessage, Structure};
+
+/** Wind profiler and RASS templates, with and without the common header sequence (301132). */
+pub(super) const PROFILER_TEMPLATES: [&str; 4] = ["309021", "309022", "309024", "309025"];
+
+/** The quality information (033002) code for suspect or bad data. */
+const SUSPECT: u64 = 1;
+
+/** A single range gate of a wind profiler or RASS. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfilerGate {
+    pub(super) height: Option<Meters>,
+    pub(super) location: Option<(f64, f64)>,
+    pub(super) wind: Option<WindUV<MetersPSec>>,
+    pub(super) wind_quality: Option<CodeValue>,
+    pub(super) vertical_velocity: Option<MetersPSec>,
+    pub(super) vertical_velocity_quality: Option<CodeValue>,
+    pub(super) virtual_temperature: Option<Celsius>,
+    pub(super) virtual_temperature_quality: Option<CodeValue>,
+    pub(super) signal_to_noise: Option<f64>,
+}
+
+impl ProfilerGate {
+    /** Height above sea level (007007). */
+    pub fn height(&self) -> Option<Meters> {
+        self.height
+    }
+
+    /** Latitude and longitude of the gate (301021), if reported. */
+    pub fn location(&self) -> Option<(f64, f64)> {
+        self.location
+    }
+
+    /** The u and v wind components (011003, 011004). */
+    pub fn wind(&self) -> Option<WindUV<MetersPSec>> {
+        self.wind
+    }
+
+    /** Quality information (033002) for the horizontal wind. */
+    pub fn wind_quality(&self) -> Option<CodeValue> {
+        self.wind_quality
+    }
+
+    /** The w component of the wind (011006). */
+    pub fn vertical_velocity(&self) -> Option<MetersPSec> {
+        self.vertical_velocity
+    }
+
+    /** Quality information (033002) for the vertical velocity. */
+    pub fn vertical_velocity_quality(&self) -> Option<CodeValue> {
+        self.vertical_velocity_quality
+    }
+
+    /** Virtual temperature from a RASS (012007). */
+    pub fn virtual_temperature(&self) -> Option<Celsius> {
+        self.virtual_temperature
+    }
+
+    /** Quality information (033002) for the virtual temperature. */
+    pub fn virtual_temperature_quality(&self) -> Option<CodeValue> {
+        self.virtual_temperature_quality
+    }
+
+    /** Signal to noise ratio in dB (021030), only reported in some national variants. */
+    pub fn signal_to_noise(&self) -> Option<f64> {
+        self.signal_to_noise
+    }
+
+    /** Whether the horizontal wind is flagged as suspect or bad. */
+    pub fn wind_is_suspect(&self) -> bool {
+        self.wind_quality.is_some_and(|q| q.code() == SUSPECT)
+    }
+}
+
+/** A wind profiler (or RASS) report with one sounding level per range gate. */
+#[derive(Clone, Debug)]
+pub struct ProfilerReport {
+    pub(super) template: &'static str,
+    pub(super) sounding: Sounding,
+    pub(super) gates: Vec<ProfilerGate>,
+}
+
+impl ProfilerReport {
+    /** The template the report came from, e.g. "309021". */
+    pub fn template(&self) -> &'static str {
+        self.template
+    }
+
+    /** A sounding with the height and wind profiles, with every reported wind. Profilers don't
+     * measure pressure, so the pressure profile is all missing values. It is still there because
+     * `Sounding::data_row` and the other functions that walk the levels stop at the end of the
+     * pressure profile.
+     */
+    pub fn sounding(&self) -> &Sounding {
+        &self.sounding
+    }
+
+    /** The same sounding with the winds flagged as suspect or bad removed. */
+    pub fn quality_controlled_sounding(&self) -> Sounding {
+        let wind = self
+            .gates
+            .iter()
+            .map(|gate| match gate.wind {
+                Some(wind) if !gate.wind_is_suspect() => WindSpdDir::<Knots>::from(wind).into(),
+                _ => none(),
+            })
+            .collect();
+
+        self.sounding.clone().with_wind_profile(wind)
+    }
+
+    /** Throw away everything but the sounding. */
+    pub fn into_sounding(self) -> Sounding {
+        self.sounding
+    }
+
+    /** Get the range gates, these line up with the sounding profiles after the surface level. So
+     * `gates()[i]` goes with `sounding().wind_profile()[i + 1]`.
+     */
+    pub fn gates(&self) -> &[ProfilerGate] {
+        &self.gates
+    }
+}
+
+/** Get the wind profiler and RASS reports (309021, 309022, 309024, 309025) from every subset of
+ * a message, usually one per station.
+ *
+ * Each range gate is a level of the sounding, with a missing pressure since profilers don't
+ * measure it, so analysis that needs the pressure won't find anything.
+ */
+pub fn profiler_reports(bufr: &BufrMessage) -> Result<Vec<ProfilerReport>, Box<dyn Error>> {
+    let mut reports = vec![];
+
+    for subset in bufr.subsets() {
+        for structure in subset {
+            if let Structure::Group(grp) = structure
+                && let Some(template) = PROFILER_TEMPLATES.iter().find(|&&t| t == grp.code())
+            {
+                reports.push(report_from_template(template, grp.items()));
+            }
+        }
+    }
+
+    if reports.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No supported wind profiler template in message.",
+        )));
+    }
+
+    Ok(reports)
+}
+
+fn report_from_template(template: &'static str, items: &[Structure]) -> ProfilerReport {
+    let mut identity = StationIdentity::default();
+    identity.extract(items);
+
+    let mut station = extract_station_location(items, StationInfo::new());
+    let mut time = LaunchTime::default();
+    let mut gates: Vec<ProfilerGate> = vec![];
+
+    for structure in items {
+        match structure {
+            // The common header sequence
+            Structure::Group(grp) if grp.code() == "301132" => {
+                station = extract_station_location(grp.items(), station);
+                extract_time_info(grp.items(), &mut time);
+            }
+            // The start and end of the averaging period, use the end.
+            Structure::Group(grp) if grp.code() == "301014" => {
+                for rep in grp.items().iter().filter_map(|s| match s {
+                    Structure::Replication(rep) => Some(rep),
+                    _ => None,
+                }) {
+                    for block in rep.blocks() {
+                        extract_time_info(block, &mut time);
+                    }
+                }
+            }
+            Structure::Replication(rep) => gates.extend(rep.blocks().map(extract_gate)),
+            _ => {}
+        }
+    }
+
+    station = identity.apply(station);
+
+    let mut snd = Sounding::new().with_station_info(station);
+    if let Some(vt) = time.to_datetime() {
+        snd = snd.with_valid_time(vt);
+    }
+
+    let pres: Vec<Optioned<HectoPascal>> = vec![none(); gates.len()];
+    let hgt: Vec<Optioned<Meters>> = gates.iter().map(|gate| gate.height.into()).collect();
+    let wind = gates
+        .iter()
+        .map(|gate| gate.wind.map(WindSpdDir::<Knots>::from).into())
+        .collect();
+
+    // Profilers don't measure pressure, but the pressure profile sets the number of levels.
+    snd = snd
+        .with_pressure_profile(pres)
+        .with_height_profile(hgt)
+        .with_wind_profile(wind);
+
+    ProfilerReport {
+        template,
+        sounding: snd,
+        gates,
+    }
+}
+
+/** Read a single range gate, quality information (033002) follows the values it applies to. */
+fn extract_gate(block: &[Structure]) -> ProfilerGate {
+    let mut gate = ProfilerGate::default();
+    let mut u: Option<f64> = None;
+    let mut v: Option<f64> = None;
+    let mut last: Option<&str> = None;
+
+    for structure in block {
+        match structure {
+            Structure::Group(grp) if grp.code() == "301021" => {
+                let mut lat = None;
+                let mut lon = None;
+                for structure in grp.items() {
+                    match structure {
+                        Structure::Element(el) if el.code() == "005001" => lat = el.get_f64_val(),
+                        Structure::Element(el) if el.code() == "006001" => lon = el.get_f64_val(),
+                        _ => {}
+                    }
+                }
+                gate.location = lat.zip(lon);
+            }
+            Structure::Element(el) => match el.code() {
+                "007007" => gate.height = el.get_f64_val().map(Meters),
+                "011003" => u = el.get_f64_val(),
+                "011004" => v = el.get_f64_val(),
+                "011006" => gate.vertical_velocity = el.get_f64_val().map(MetersPSec),
+                "012007" => gate.virtual_temperature = el.get_f64_val().map(Kelvin).map(Celsius::from),
+                "021030" => gate.signal_to_noise = el.get_f64_val(),
+                "033002" => {
+                    let quality = CodeValue::from_element(el);
+                    match last {
+                        Some("011003" | "011004" | "011110" | "011111") => gate.wind_quality = quality,
+                        Some("011006" | "011112") => gate.vertical_velocity_quality = quality,
+                        Some("012007" | "012008") => gate.virtual_temperature_quality = quality,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        if let Structure::Element(el) = structure
+            && el.code() != "033002"
+        {
+            last = Some(el.code());
+        }
+    }
+
+    gate.wind = u.zip(v).map(|(u, v)| WindUV {
+        u: MetersPSec(u),
+        v: MetersPSec(v),
+    });
+
+    gate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Element, Group, Replication, Value};
+
+    fn element(fxy: &'static str, val: Value) -> Structure {
+        Structure::Element(Element::new(val, "", "", fxy))
+    }
+
+    #[test]
+    fn test_report_from_template() {
+        let mut header = Group::new_with_capacity(5, "", "301132");
+        for (fxy, val) in [("004001", 2020), ("004002", 1), ("004003", 1), ("004004", 12), ("004005", 0)] {
+            header.push(element(fxy, Value::Numeric(val)));
+        }
+
+        let mut rep = Replication::new_with_capacity(10, 5);
+        for (height, u, quality) in [(500.0, 5.0, 0), (1000.0, 10.0, 1)] {
+            rep.push(element("007007", Value::Float(height)));
+            rep.push(element("011003", Value::Float(u)));
+            rep.push(element("011004", Value::Float(0.0)));
+            rep.push(element("033002", Value::Code(quality)));
+            rep.push(element("011006", Value::Float(0.1)));
+        }
+
+        let items = vec![Structure::Group(header), Structure::Replication(rep)];
+        let report = report_from_template("309021", &items);
+        let snd = report.sounding();
+
+        // The surface level and one level per gate
+        assert_eq!(snd.pressure_profile().len(), 3);
+        assert!(snd.pressure_profile().iter().all(|p| p.is_none()));
+        assert_eq!(snd.height_profile().len(), 3);
+        assert_eq!(snd.height_profile()[2].into_option(), Some(Meters(1000.0)));
+        assert!(snd.data_row(2).is_some_and(|row| row.wind.is_some()));
+        assert!(snd.valid_time().is_some());
+
+        assert_eq!(report.gates().len(), 2);
+        assert!(!report.gates()[0].wind_is_suspect());
+        assert!(report.gates()[1].wind_is_suspect());
+        assert_eq!(report.gates()[1].vertical_velocity(), Some(MetersPSec(0.1)));
+
+        let qc = report.quality_controlled_sounding();
+        assert!(qc.wind_profile()[1].is_some());
+        assert!(qc.wind_profile()[2].is_none());
+    }
+}
@@ -0,0 +1,331 @@
+use metfor::{Celsius, HectoPascal, Knots, Meters, WindSpdDir};
+use optional::{Optioned, none};
+
+use super::{LevelInfo, RadiosondeReport, VerticalSignificance};
+
+/** Dew points no more than this far above the temperature are treated as rounding errors. */
+const DEW_POINT_TOLERANCE: f64 = 0.5;
+
+/** A change made to a sounding by `RadiosondeReport::quality_control`. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum QcChange {
+    /** A level without a pressure was removed, it can't be placed in the profile. */
+    RemovedMissingPressure { height: Option<Meters> },
+    /** The levels were not in order of decreasing pressure, so they were sorted. */
+    Sorted,
+    /** A level with the same pressure (to 0.1 hPa) as an earlier level was merged into it. The
+     * first non-missing value for each variable is kept.
+     */
+    MergedDuplicate { pressure: HectoPascal },
+    /** A height that was not above the height of the level below it was removed. */
+    RemovedHeight { pressure: HectoPascal, height: Meters },
+    /** A dew point slightly above the temperature was set to the temperature. */
+    ClampedDewPoint { pressure: HectoPascal, temperature: Celsius, dew_point: Celsius },
+    /** A dew point well above the temperature was removed. */
+    RemovedDewPoint { pressure: HectoPascal, temperature: Celsius, dew_point: Celsius },
+    /** A level with no data left besides the pressure was removed. */
+    RemovedEmpty { pressure: HectoPascal },
+}
+
+/** A level above the surface while it is being checked. */
+struct QcLevel {
+    pres: Optioned<HectoPascal>,
+    temp: Optioned<Celsius>,
+    dewp: Optioned<Celsius>,
+    hgt: Optioned<Meters>,
+    wind: Optioned<WindSpdDir<Knots>>,
+    info: LevelInfo,
+    /** Index of the level in the profiles before quality control. */
+    original_index: usize,
+}
+
+impl QcLevel {
+    fn pressure(&self) -> HectoPascal {
+        self.pres.unwrap()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.temp.is_none() && self.dewp.is_none() && self.hgt.is_none() && self.wind.is_none()
+    }
+}
+
+impl RadiosondeReport {
+    /** Make the sounding physically consistent and report what was changed.
+     *
+     * Levels are reported in the order the sonde measured them, which isn't always the order
+     * `sounding-analysis` expects. This removes levels without a pressure, sorts the rest by
+     * decreasing pressure, and merges levels with the same pressure. Then heights that don't
+     * increase with decreasing pressure and dew points above the temperature are removed, along
+     * with any levels left with nothing but a pressure. Levels below the station pressure are
+     * already dropped when the report is extracted.
+     *
+     * The per-level information and the wind shear max wind level indexes are updated to match.
+     */
+    pub fn quality_control(&mut self) -> Vec<QcChange> {
+        let mut changes = vec![];
+
+        let snd = std::mem::take(&mut self.sounding);
+        let station_pressure = snd.station_pressure().into_option();
+
+        let mut levels: Vec<QcLevel> = Vec::with_capacity(self.levels.len());
+        for (i, info) in self.levels.iter().enumerate().skip(1) {
+            let lvl = QcLevel {
+                pres: snd.pressure_profile().get(i).copied().unwrap_or_else(none),
+                temp: snd.temperature_profile().get(i).copied().unwrap_or_else(none),
+                dewp: snd.dew_point_profile().get(i).copied().unwrap_or_else(none),
+                hgt: snd.height_profile().get(i).copied().unwrap_or_else(none),
+                wind: snd.wind_profile().get(i).copied().unwrap_or_else(none),
+                info: info.clone(),
+                original_index: i,
+            };
+
+            match lvl.pres.into_option() {
+                None => changes.push(QcChange::RemovedMissingPressure { height: lvl.hgt.into_option() }),
+                Some(_) => levels.push(lvl),
+            }
+        }
+
+        if levels.windows(2).any(|pair| pair[0].pressure() < pair[1].pressure()) {
+            // Stable sort, so the first level reported comes first among duplicates.
+            levels.sort_by(|a, b| b.pressure().0.total_cmp(&a.pressure().0));
+            changes.push(QcChange::Sorted);
+        }
+
+        // Pressure is reported in units of 10 Pa, so compare levels at 0.1 hPa resolution.
+        let level_key = |lvl: &QcLevel| (lvl.pressure().0 * 10.0).round() as i64;
+
+        let mut merged: Vec<QcLevel> = Vec::with_capacity(levels.len());
+        let mut index_map: Vec<(usize, usize)> = vec![];
+        for lvl in levels {
+            match merged.last_mut() {
+                Some(prev) if level_key(prev) == level_key(&lvl) => {
+                    if prev.temp.is_none() {
+                        prev.temp = lvl.temp;
+                    }
+                    if prev.dewp.is_none() {
+                        prev.dewp = lvl.dewp;
+                        prev.info.dew_point_source = lvl.info.dew_point_source;
+                    }
+                    if prev.hgt.is_none() {
+                        prev.hgt = lvl.hgt;
+                    }
+                    if prev.wind.is_none() {
+                        prev.wind = lvl.wind;
+                    }
+                    prev.info.significance = match (prev.info.significance, lvl.info.significance) {
+                        (Some(a), Some(b)) => Some(VerticalSignificance::from_008042(u64::from(a.raw() | b.raw()))),
+                        (a, b) => a.or(b),
+                    };
+
+                    index_map.push((lvl.original_index, prev.original_index));
+                    changes.push(QcChange::MergedDuplicate { pressure: lvl.pressure() });
+                }
+                _ => merged.push(lvl),
+            }
+        }
+        let mut levels = merged;
+
+        let mut below: Option<Meters> = snd.height_profile().first().and_then(|z| z.into_option());
+        for lvl in levels.iter_mut() {
+            if let Some(height) = lvl.hgt.into_option() {
+                if below.is_some_and(|b| height <= b) {
+                    lvl.hgt = none();
+                    changes.push(QcChange::RemovedHeight { pressure: lvl.pressure(), height });
+                } else {
+                    below = Some(height);
+                }
+            }
+        }
+
+        let mut sfc_dewp = snd.sfc_dew_point();
+        if let Some(pressure) = station_pressure
+            && let Some(change) = check_dew_point(pressure, snd.sfc_temperature(), &mut sfc_dewp)
+        {
+            changes.push(change);
+        }
+        for lvl in levels.iter_mut() {
+            if let Some(change) = check_dew_point(lvl.pressure(), lvl.temp, &mut lvl.dewp) {
+                changes.push(change);
+            }
+        }
+
+        // Don't keep a moisture source for dew points that were removed.
+        if sfc_dewp.is_none()
+            && let Some(sfc) = self.levels.first_mut()
+        {
+            sfc.dew_point_source = None;
+        }
+        for lvl in levels.iter_mut().filter(|lvl| lvl.dewp.is_none()) {
+            lvl.info.dew_point_source = None;
+        }
+
+        levels.retain(|lvl| {
+            if lvl.is_empty() {
+                changes.push(QcChange::RemovedEmpty { pressure: lvl.pressure() });
+            }
+            !lvl.is_empty()
+        });
+
+        // Point the wind shear at the new index of its max wind level, following merged levels to
+        // the level they were merged into.
+        for shear in self.wind_shear.iter_mut() {
+            shear.max_wind_level = shear.max_wind_level.and_then(|old| {
+                let old = index_map.iter().find(|(from, _)| *from == old).map_or(old, |&(_, to)| to);
+                levels
+                    .iter()
+                    .position(|lvl| lvl.original_index == old)
+                    .map(|i| i + 1)
+            });
+        }
+
+        if levels.is_empty() {
+            self.levels.clear();
+        } else {
+            self.levels.truncate(1);
+        }
+        self.levels.extend(levels.iter().map(|lvl| lvl.info.clone()));
+
+        self.sounding = snd
+            .with_sfc_dew_point(sfc_dewp)
+            .with_pressure_profile(levels.iter().map(|lvl| lvl.pres).collect())
+            .with_temperature_profile(levels.iter().map(|lvl| lvl.temp).collect())
+            .with_dew_point_profile(levels.iter().map(|lvl| lvl.dewp).collect())
+            .with_height_profile(levels.iter().map(|lvl| lvl.hgt).collect())
+            .with_wind_profile(levels.iter().map(|lvl| lvl.wind).collect());
+
+        changes
+    }
+}
+
+/** Clamp or remove a dew point that is above the temperature. */
+fn check_dew_point(
+    pressure: HectoPascal,
+    temp: Optioned<Celsius>,
+    dewp: &mut Optioned<Celsius>,
+) -> Option<QcChange> {
+    let (temperature, dew_point) = temp.into_option().zip(dewp.into_option())?;
+
+    if dew_point <= temperature {
+        None
+    } else if dew_point.0 - temperature.0 <= DEW_POINT_TOLERANCE {
+        *dewp = temp;
+        Some(QcChange::ClampedDewPoint { pressure, temperature, dew_point })
+    } else {
+        *dewp = none();
+        Some(QcChange::RemovedDewPoint { pressure, temperature, dew_point })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::easy_api::{RadiosondeMetadata, WindShear};
+    use sounding_analysis::{Sounding, StationInfo};
+
+    const STANDARD_LEVEL: u64 = 1 << 16;
+    const MAX_WIND: u64 = 1 << 14;
+
+    /** Pressure, temperature, dew point, height, wind speed, and significance above the surface. */
+    type Level = (Option<f64>, Option<f64>, Option<f64>, Option<f64>, Option<f64>, u64);
+
+    fn report(levels: &[Level], wind_shear: Vec<WindShear>) -> RadiosondeReport {
+        let sounding = Sounding::new()
+            .with_station_info(StationInfo::new().with_elevation(Meters(1000.0)))
+            .with_station_pressure(HectoPascal(900.0))
+            .with_sfc_temperature(Celsius(20.0))
+            .with_sfc_dew_point(Celsius(20.4))
+            .with_pressure_profile(levels.iter().map(|lvl| lvl.0.map(HectoPascal).into()).collect())
+            .with_temperature_profile(levels.iter().map(|lvl| lvl.1.map(Celsius).into()).collect())
+            .with_dew_point_profile(levels.iter().map(|lvl| lvl.2.map(Celsius).into()).collect())
+            .with_height_profile(levels.iter().map(|lvl| lvl.3.map(Meters).into()).collect())
+            .with_wind_profile(
+                levels
+                    .iter()
+                    .map(|lvl| lvl.4.map(|speed| WindSpdDir { speed: Knots(speed), direction: 270.0 }).into())
+                    .collect(),
+            );
+
+        let mut info = vec![LevelInfo::default()];
+        info.extend(levels.iter().map(|lvl| LevelInfo {
+            significance: Some(VerticalSignificance::from_008042(lvl.5)),
+            ..LevelInfo::default()
+        }));
+
+        RadiosondeReport {
+            sounding,
+            levels: info,
+            metadata: RadiosondeMetadata::default(),
+            wind_shear,
+            wigos_id: None,
+        }
+    }
+
+    #[test]
+    fn test_quality_control() {
+        let levels: [Level; 6] = [
+            (Some(850.0), Some(15.0), Some(10.0), Some(1500.0), None, STANDARD_LEVEL),
+            (Some(700.0), Some(0.0), Some(5.0), Some(3000.0), Some(20.0), STANDARD_LEVEL),
+            (Some(800.0), Some(8.0), Some(2.0), Some(1400.0), None, 0),
+            (Some(850.0), None, None, None, Some(30.0), MAX_WIND),
+            (None, None, None, Some(5000.0), None, 0),
+            (Some(500.0), None, None, None, None, 0),
+        ];
+        let shear = WindShear {
+            max_wind_level: Some(4),
+            ..WindShear::default()
+        };
+
+        let mut report = report(&levels, vec![shear]);
+        let changes = report.quality_control();
+
+        assert_eq!(
+            changes,
+            vec![
+                QcChange::RemovedMissingPressure { height: Some(Meters(5000.0)) },
+                QcChange::Sorted,
+                QcChange::MergedDuplicate { pressure: HectoPascal(850.0) },
+                QcChange::RemovedHeight { pressure: HectoPascal(800.0), height: Meters(1400.0) },
+                QcChange::ClampedDewPoint {
+                    pressure: HectoPascal(900.0),
+                    temperature: Celsius(20.0),
+                    dew_point: Celsius(20.4),
+                },
+                QcChange::RemovedDewPoint {
+                    pressure: HectoPascal(700.0),
+                    temperature: Celsius(0.0),
+                    dew_point: Celsius(5.0),
+                },
+                QcChange::RemovedEmpty { pressure: HectoPascal(500.0) },
+            ]
+        );
+
+        let snd = report.sounding();
+        let pres: Vec<Option<f64>> = snd.pressure_profile().iter().map(|p| p.into_option().map(|p| p.0)).collect();
+        assert_eq!(pres, vec![Some(900.0), Some(850.0), Some(800.0), Some(700.0)]);
+        assert_eq!(snd.sfc_dew_point().into_option(), Some(Celsius(20.0)));
+        assert_eq!(snd.height_profile()[2].into_option(), None);
+        assert_eq!(snd.dew_point_profile()[3].into_option(), None);
+
+        // The duplicate 850 hPa level fills in the wind and its significance.
+        assert_eq!(snd.wind_profile()[1].into_option().map(|w| w.speed), Some(Knots(30.0)));
+        assert_eq!(report.levels().len(), 4);
+        let significance = report.levels()[1].significance().unwrap();
+        assert!(significance.is_standard_level() && significance.is_max_wind());
+        assert_eq!(report.wind_shear()[0].max_wind_level(), Some(1));
+    }
+
+    #[test]
+    fn test_quality_control_no_changes() {
+        let levels: [Level; 2] = [
+            (Some(850.0), Some(15.0), Some(10.0), Some(1500.0), Some(10.0), STANDARD_LEVEL),
+            (Some(700.0), Some(0.0), Some(-5.0), Some(3000.0), Some(20.0), STANDARD_LEVEL),
+        ];
+
+        let mut report = report(&levels, vec![]);
+        report.sounding = report.sounding.with_sfc_dew_point(Celsius(15.0));
+
+        assert!(report.quality_control().is_empty());
+        assert_eq!(report.sounding().pressure_profile().len(), 3);
+        assert_eq!(report.levels().len(), 3);
+    }
+}
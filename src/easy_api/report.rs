@@ -0,0 +1,199 @@
+use chrono::{NaiveDateTime, TimeDelta};
+use metfor::{HectoPascal, Meters, MetersPSec};
+use sounding_analysis::{DataRow, Sounding};
+
+use super::{MoistureSource, RadiosondeMetadata, VerticalSignificance, WigosId};
+
+/** A sounding along with the per-level data that doesn't fit in a `Sounding`. */
+#[derive(Clone, Debug)]
+pub struct RadiosondeReport {
+    pub(super) sounding: Sounding,
+    pub(super) levels: Vec<LevelInfo>,
+    pub(super) metadata: RadiosondeMetadata,
+    pub(super) wind_shear: Vec<WindShear>,
+    pub(super) wigos_id: Option<WigosId>,
+}
+
+impl RadiosondeReport {
+    /** Get the sounding. */
+    pub fn sounding(&self) -> &Sounding {
+        &self.sounding
+    }
+
+    /** Throw away everything but the sounding. */
+    pub fn into_sounding(self) -> Sounding {
+        self.sounding
+    }
+
+    /** Get the per-level information.
+     *
+     * These line up with the profiles in the sounding, so the first entry is for the surface
+     * (launch site) and `levels()[i]` goes with `sounding().pressure_profile()[i]`.
+     */
+    pub fn levels(&self) -> &[LevelInfo] {
+        &self.levels
+    }
+
+    /** Get the radiosonde and launch metadata. */
+    pub fn metadata(&self) -> &RadiosondeMetadata {
+        &self.metadata
+    }
+
+    /** Get the WIGOS station identifier, if there was one in the message. */
+    pub fn wigos_id(&self) -> Option<&WigosId> {
+        self.wigos_id.as_ref()
+    }
+
+    /** Get the wind shear reported around the maximum wind levels. */
+    pub fn wind_shear(&self) -> &[WindShear] {
+        &self.wind_shear
+    }
+
+    /** Get the data rows for all the levels with a significance matching the predicate. */
+    pub fn levels_where<'a>(
+        &'a self,
+        pred: impl Fn(VerticalSignificance) -> bool + 'a,
+    ) -> impl Iterator<Item = DataRow> + 'a {
+        self.levels
+            .iter()
+            .enumerate()
+            .filter(move |(_, lvl)| lvl.significance.is_some_and(&pred))
+            .filter_map(|(i, _)| self.sounding.data_row(i))
+    }
+
+    /** The level flagged as the surface. */
+    pub fn surface_level(&self) -> Option<DataRow> {
+        self.levels_where(|sig| sig.is_surface()).next()
+    }
+
+    /** Levels flagged as standard (mandatory) pressure levels. */
+    pub fn standard_levels(&self) -> Vec<DataRow> {
+        self.levels_where(|sig| sig.is_standard_level()).collect()
+    }
+
+    /** Levels flagged as a tropopause. */
+    pub fn tropopause_levels(&self) -> Vec<DataRow> {
+        self.levels_where(|sig| sig.is_tropopause()).collect()
+    }
+
+    /** Levels flagged as a maximum wind level. */
+    pub fn max_wind_levels(&self) -> Vec<DataRow> {
+        self.levels_where(|sig| sig.is_max_wind()).collect()
+    }
+
+    /** Levels flagged as significant for temperature, humidity, or wind. */
+    pub fn significant_levels(&self) -> Vec<DataRow> {
+        self.levels_where(|sig| {
+            sig.is_significant_temperature() || sig.is_significant_humidity() || sig.is_significant_wind()
+        })
+        .collect()
+    }
+}
+
+/** The position of the balloon (or dropsonde) when it reported a level. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelInfo {
+    pub(super) time_offset: Option<TimeDelta>,
+    pub(super) time: Option<NaiveDateTime>,
+    pub(super) displacement: Option<(f64, f64)>,
+    pub(super) location: Option<(f64, f64)>,
+    pub(super) significance: Option<VerticalSignificance>,
+    pub(super) derived_height: bool,
+    pub(super) dew_point_source: Option<MoistureSource>,
+}
+
+impl LevelInfo {
+    /** Time since launch (004086). */
+    pub fn time_offset(&self) -> Option<TimeDelta> {
+        self.time_offset
+    }
+
+    /** Launch time plus the time offset. */
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        self.time
+    }
+
+    /** Latitude and longitude displacement from the launch site in degrees (005015, 006015). */
+    pub fn displacement(&self) -> Option<(f64, f64)> {
+        self.displacement
+    }
+
+    /** Latitude and longitude, the launch site plus the displacement. */
+    pub fn location(&self) -> Option<(f64, f64)> {
+        self.location
+    }
+
+    /** Vertical sounding significance (008042, or 008001 for CLIMAT TEMP). */
+    pub fn significance(&self) -> Option<VerticalSignificance> {
+        self.significance
+    }
+
+    /** Whether the height was computed by `RadiosondeReport::fill_missing_heights` instead of
+     * reported.
+     */
+    pub fn height_is_derived(&self) -> bool {
+        self.derived_height
+    }
+
+    /** Which moisture variable the dew point came from, `None` if there is no dew point. */
+    pub fn dew_point_source(&self) -> Option<MoistureSource> {
+        self.dew_point_source
+    }
+}
+
+/** Absolute wind shear in the 1 km layers below and above a maximum wind level (303051, 303053). */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindShear {
+    pub(super) pressure: Option<HectoPascal>,
+    pub(super) height: Option<Meters>,
+    pub(super) time_offset: Option<TimeDelta>,
+    pub(super) displacement: Option<(f64, f64)>,
+    pub(super) significance: Option<VerticalSignificance>,
+    pub(super) below: Option<MetersPSec>,
+    pub(super) above: Option<MetersPSec>,
+    pub(super) max_wind_level: Option<usize>,
+}
+
+impl WindShear {
+    /** Pressure of the maximum wind level (templates with pressure as the vertical coordinate). */
+    pub fn pressure(&self) -> Option<HectoPascal> {
+        self.pressure
+    }
+
+    /** Height of the maximum wind level (templates with height as the vertical coordinate). */
+    pub fn height(&self) -> Option<Meters> {
+        self.height
+    }
+
+    /** Time since launch (004086). */
+    pub fn time_offset(&self) -> Option<TimeDelta> {
+        self.time_offset
+    }
+
+    /** Latitude and longitude displacement from the launch site in degrees (005015, 006015). */
+    pub fn displacement(&self) -> Option<(f64, f64)> {
+        self.displacement
+    }
+
+    /** Vertical sounding significance (008042). */
+    pub fn significance(&self) -> Option<VerticalSignificance> {
+        self.significance
+    }
+
+    /** Absolute wind shear in the 1 km layer below (011061). */
+    pub fn below(&self) -> Option<MetersPSec> {
+        self.below
+    }
+
+    /** Absolute wind shear in the 1 km layer above (011062). */
+    pub fn above(&self) -> Option<MetersPSec> {
+        self.above
+    }
+
+    /** Index of the matching maximum wind level in the sounding profiles and
+     * `RadiosondeReport::levels()`, if there is one at the same pressure or height.
+     */
+    pub fn max_wind_level(&self) -> Option<usize> {
+        self.max_wind_level
+    }
+}
@@ -0,0 +1,234 @@
+use std::error::Error;
+
+use metfor::{Celsius, HectoPascal, Kelvin, Meters};
+use optional::Optioned;
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::{LaunchTime, Moisture, extract_time_info};
+use crate::types::{BufrMessage, Element, Structure};
+
+/** Vertical significance (satellite observations, 008003) of the surface. */
+const SURFACE_SIGNIFICANCE: u64 = 0;
+
+/** Build a sounding for every field of view (subset) of a satellite retrieval product, like NUCAPS
+ * or IASI level 2 profiles.
+ *
+ * These products don't have a WMO template, so the levels are the repetitions of any replication
+ * with a pressure (007004 or 010004), and the location and time are the first values outside of
+ * the replications. The surface pressure is the first 010004 outside of the replications that
+ * follows a vertical significance (008003) of surface, other pressures like the cloud top aren't
+ * used. Temperature (012101 or 012001), geopotential height (007009 or 010009), and moisture
+ * (012103, 013001, 013002, or 013003) are read from each level. The levels are sorted from the
+ * surface up, and levels below the surface pressure, which most retrieval grids have, are removed.
+ */
+pub fn retrieved_soundings(bufr: &BufrMessage) -> Result<Vec<Sounding>, Box<dyn Error>> {
+    let soundings: Vec<Sounding> = bufr
+        .subsets()
+        .iter()
+        .filter_map(|subset| sounding_from_subset(subset))
+        .collect();
+
+    if soundings.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No retrieved profiles in message.",
+        )));
+    }
+
+    Ok(soundings)
+}
+
+/** A single level of a retrieved profile. */
+#[derive(Default)]
+struct RetrievedLevel {
+    pressure: Option<HectoPascal>,
+    temperature: Option<Celsius>,
+    height: Option<Meters>,
+    moisture: Moisture,
+}
+
+/** The values outside the levels. */
+#[derive(Default)]
+struct FieldOfView {
+    satellite: Option<u64>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    time: LaunchTime,
+    surface_pressure: Option<HectoPascal>,
+    /** Whether the last vertical significance (008003) was the surface. */
+    at_surface: bool,
+}
+
+fn sounding_from_subset(items: &[Structure]) -> Option<Sounding> {
+    let mut fov = FieldOfView::default();
+    let mut levels: Vec<RetrievedLevel> = vec![];
+    collect(items, &mut fov, &mut levels);
+
+    levels.retain(|lvl| {
+        lvl.pressure
+            .is_some_and(|p| fov.surface_pressure.is_none_or(|sfc| p <= sfc))
+    });
+    if levels.is_empty() {
+        return None;
+    }
+    levels.sort_by(|a, b| b.pressure.unwrap().0.total_cmp(&a.pressure.unwrap().0));
+
+    let mut station = StationInfo::new();
+    if let Some(location) = fov.lat.zip(fov.lon) {
+        station = station.with_lat_lon(location);
+    }
+
+    let mut snd = Sounding::new().with_station_info(station);
+    if let Some(vt) = fov.time.to_datetime() {
+        snd = snd.with_valid_time(vt);
+    }
+    if let Some(satellite) = fov.satellite {
+        snd = snd.with_source_description(format!("Satellite retrieval, satellite {}", satellite));
+    }
+
+    let dewp = levels
+        .iter()
+        .map(|lvl| {
+            lvl.moisture
+                .dew_point(lvl.temperature.into(), lvl.pressure.into())
+                .map(|(td, _)| td)
+                .into()
+        })
+        .collect();
+
+    let snd = snd
+        .with_station_pressure(Optioned::<HectoPascal>::from(fov.surface_pressure))
+        .with_pressure_profile(levels.iter().map(|lvl| lvl.pressure.into()).collect())
+        .with_temperature_profile(levels.iter().map(|lvl| lvl.temperature.into()).collect())
+        .with_dew_point_profile(dewp)
+        .with_height_profile(levels.iter().map(|lvl| lvl.height.into()).collect());
+
+    Some(snd)
+}
+
+fn collect(items: &[Structure], fov: &mut FieldOfView, levels: &mut Vec<RetrievedLevel>) {
+    extract_time_info(items, &mut fov.time);
+
+    for structure in items {
+        match structure {
+            Structure::Element(el) => match el.code() {
+                "001007" if fov.satellite.is_none() => fov.satellite = el.get_code_val(),
+                "005001" | "005002" if fov.lat.is_none() => fov.lat = el.get_f64_val(),
+                "006001" | "006002" if fov.lon.is_none() => fov.lon = el.get_f64_val(),
+                "008003" => fov.at_surface = el.get_code_val() == Some(SURFACE_SIGNIFICANCE),
+                "010004" if fov.at_surface && fov.surface_pressure.is_none() => fov.surface_pressure = pressure(el),
+                _ => {}
+            },
+            Structure::Group(grp) => collect(grp.items(), fov, levels),
+            Structure::Replication(rep) => {
+                for block in rep.blocks() {
+                    let level = extract_level(block);
+                    if level.pressure.is_some() {
+                        levels.push(level);
+                    } else {
+                        collect(block, fov, levels);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pressure(el: &Element) -> Option<HectoPascal> {
+    el.get_f64_val().map(|p| HectoPascal(p / 100.0))
+}
+
+/** Read the first value of each element in a level, including those in sequences. */
+fn extract_level(block: &[Structure]) -> RetrievedLevel {
+    let mut level = RetrievedLevel::default();
+    extract_level_elements(block, &mut level);
+    level
+}
+
+fn extract_level_elements(items: &[Structure], level: &mut RetrievedLevel) {
+    let kelvin = |el: &Element| el.get_f64_val().map(Kelvin).map(Celsius::from);
+
+    for structure in items {
+        let el = match structure {
+            Structure::Element(el) => el,
+            Structure::Group(grp) => {
+                extract_level_elements(grp.items(), level);
+                continue;
+            }
+            Structure::Replication(_) => continue,
+        };
+
+        let moisture = &mut level.moisture;
+        match el.code() {
+            "007004" | "010004" if level.pressure.is_none() => level.pressure = pressure(el),
+            "012101" | "012001" if level.temperature.is_none() => level.temperature = kelvin(el),
+            "007009" | "010009" if level.height.is_none() => level.height = el.get_f64_val().map(Meters),
+            "012103" if moisture.dew_point.is_none() => moisture.dew_point = kelvin(el),
+            "013001" if moisture.specific_humidity.is_none() => moisture.specific_humidity = el.get_f64_val(),
+            "013002" if moisture.mixing_ratio.is_none() => moisture.mixing_ratio = el.get_f64_val(),
+            "013003" if moisture.relative_humidity.is_none() => moisture.relative_humidity = el.get_f64_val(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        read_bufr_message_from_slice,
+        test_util::{Bits, message},
+    };
+
+    #[test]
+    fn test_retrieved_soundings() {
+        let descriptors = [
+            "001007", "005001", "006001", "004001", "004002", "004003", "004004", "004005", "008003", "010004",
+            "008003", "010004", "008003", "102000", "031001", "007004", "012101",
+        ];
+
+        let mut data = Bits::new();
+        data.compressed("001007", &[Some(224.0), Some(224.0)])
+            .compressed("005001", &[Some(40.0), Some(41.0)])
+            .compressed("006001", &[Some(-100.0), Some(-101.0)])
+            .compressed("004001", &[Some(2020.0), Some(2020.0)])
+            .compressed("004002", &[Some(1.0), Some(1.0)])
+            .compressed("004003", &[Some(1.0), Some(1.0)])
+            .compressed("004004", &[Some(12.0), Some(12.0)])
+            .compressed("004005", &[Some(30.0), Some(31.0)])
+            // The cloud top pressure comes first.
+            .compressed("008003", &[Some(2.0), Some(2.0)])
+            .compressed("010004", &[Some(30_000.0), Some(40_000.0)])
+            .compressed("008003", &[Some(0.0), Some(0.0)])
+            .compressed("010004", &[Some(95_000.0), Some(85_000.0)])
+            .compressed("008003", &[None, None])
+            .compressed("031001", &[Some(4.0), Some(4.0)]);
+        // From the top down
+        for (p, t) in [(20_000.0, 220.0), (50_000.0, 250.0), (90_000.0, 285.0), (100_000.0, 290.0)] {
+            data.compressed("007004", &[Some(p), Some(p)])
+                .compressed("012101", &[Some(t), Some(t + 1.0)]);
+        }
+
+        let bytes = message(&descriptors, &data, 2, 3, true);
+        let bufr = read_bufr_message_from_slice(&bytes).unwrap();
+        let soundings = retrieved_soundings(&bufr).unwrap();
+        assert_eq!(soundings.len(), 2);
+
+        let pressures = |snd: &Sounding| -> Vec<Option<f64>> {
+            snd.pressure_profile().iter().map(|p| p.into_option().map(|p| p.0)).collect()
+        };
+
+        let first = &soundings[0];
+        assert_eq!(first.station_pressure().into_option(), Some(HectoPascal(950.0)));
+        assert_eq!(pressures(first), vec![Some(950.0), Some(900.0), Some(500.0), Some(200.0)]);
+        assert_eq!(first.station_info().location(), Some((40.0, -100.0)));
+        assert_eq!(first.source_description(), Some("Satellite retrieval, satellite 224"));
+
+        let second = &soundings[1];
+        assert_eq!(second.station_pressure().into_option(), Some(HectoPascal(850.0)));
+        assert_eq!(pressures(second), vec![Some(850.0), Some(500.0), Some(200.0)]);
+        let temps: Vec<Option<f64>> = second.temperature_profile().iter().map(|t| t.into_option().map(|t| t.0)).collect();
+        assert_eq!(temps.len(), 3);
+        assert!((temps[1].unwrap() - (251.0 - 273.15)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,170 @@
+/** Extended vertical sounding significance (flag table 008042) of a level.
+ *
+ * Levels from the older 7 bit flag table 008001, used by CLIMAT TEMP, are translated into the
+ * equivalent 008042 flags.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VerticalSignificance(u32);
+
+impl VerticalSignificance {
+    const WIDTH_008042: u32 = 18;
+    const WIDTH_008001: u32 = 7;
+
+    /** Create from the raw value of a 008042 element. */
+    pub fn from_008042(raw: u64) -> Self {
+        VerticalSignificance((raw & ((1 << Self::WIDTH_008042) - 1)) as u32)
+    }
+
+    /** Create from the raw value of a 008001 element. */
+    pub fn from_008001(raw: u64) -> Self {
+        // 008001 bit => 008042 bits
+        const MAPPING: [(u32, &[u32]); 6] = [(1, &[1]), (2, &[2]), (3, &[3]), (4, &[4]), (5, &[5, 6]), (6, &[7])];
+
+        let mut flags = 0;
+        for (bit_008001, bits_008042) in MAPPING {
+            if raw >> (Self::WIDTH_008001 - bit_008001) & 1 == 1 {
+                for bit in bits_008042 {
+                    flags |= 1 << (Self::WIDTH_008042 - bit);
+                }
+            }
+        }
+
+        VerticalSignificance(flags)
+    }
+
+    /** The value as it would be encoded in a 008042 element. */
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+    /** Check a flag by its bit number in the WMO flag table, bit 1 is the most significant. */
+    pub fn bit(&self, bit_number: u32) -> bool {
+        debug_assert!((1..=Self::WIDTH_008042).contains(&bit_number));
+        self.0 >> (Self::WIDTH_008042 - bit_number) & 1 == 1
+    }
+
+    /** No flags set, an ordinary level in a high resolution sounding. */
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_surface(&self) -> bool {
+        self.bit(1)
+    }
+
+    pub fn is_standard_level(&self) -> bool {
+        self.bit(2)
+    }
+
+    pub fn is_tropopause(&self) -> bool {
+        self.bit(3)
+    }
+
+    pub fn is_max_wind(&self) -> bool {
+        self.bit(4)
+    }
+
+    pub fn is_significant_temperature(&self) -> bool {
+        self.bit(5)
+    }
+
+    pub fn is_significant_humidity(&self) -> bool {
+        self.bit(6)
+    }
+
+    pub fn is_significant_wind(&self) -> bool {
+        self.bit(7)
+    }
+
+    pub fn is_beginning_of_missing_temperature(&self) -> bool {
+        self.bit(8)
+    }
+
+    pub fn is_end_of_missing_temperature(&self) -> bool {
+        self.bit(9)
+    }
+
+    pub fn is_beginning_of_missing_humidity(&self) -> bool {
+        self.bit(10)
+    }
+
+    pub fn is_end_of_missing_humidity(&self) -> bool {
+        self.bit(11)
+    }
+
+    pub fn is_beginning_of_missing_wind(&self) -> bool {
+        self.bit(12)
+    }
+
+    pub fn is_end_of_missing_wind(&self) -> bool {
+        self.bit(13)
+    }
+
+    pub fn is_top_of_wind_sounding(&self) -> bool {
+        self.bit(14)
+    }
+
+    pub fn is_regional(&self) -> bool {
+        self.bit(15)
+    }
+
+    pub fn is_freezing_level(&self) -> bool {
+        self.bit(16)
+    }
+
+    /** A pressure level originally indicated by height as the vertical coordinate. */
+    pub fn is_from_height(&self) -> bool {
+        self.bit(17)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_008042() {
+        // The surface level in test-data/2017083115.bufr
+        let sig = VerticalSignificance::from_008042(0b10_0011_1000_0000_0000);
+        assert!(sig.is_surface());
+        assert!(!sig.is_standard_level());
+        assert!(sig.is_significant_temperature());
+        assert!(sig.is_significant_humidity());
+        assert!(sig.is_significant_wind());
+        assert!(!sig.is_beginning_of_missing_temperature());
+
+        let sig = VerticalSignificance::from_008042(1 << 16);
+        assert!(sig.is_standard_level());
+        assert!(!sig.is_surface());
+
+        assert!(VerticalSignificance::from_008042(0).is_empty());
+        assert!(VerticalSignificance::from_008042(1 << 1).is_from_height());
+        assert!(VerticalSignificance::from_008042(1).bit(18));
+        assert_eq!(VerticalSignificance::from_008042(u64::MAX).raw(), (1 << 18) - 1);
+    }
+
+    #[test]
+    fn test_from_008001() {
+        assert_eq!(VerticalSignificance::from_008001(0b100_0000), VerticalSignificance::from_008042(1 << 17));
+        assert!(VerticalSignificance::from_008001(0b100_0000).is_surface());
+        assert!(VerticalSignificance::from_008001(0b010_0000).is_standard_level());
+        assert!(VerticalSignificance::from_008001(0b001_0000).is_tropopause());
+        assert!(VerticalSignificance::from_008001(0b000_1000).is_max_wind());
+
+        let sig = VerticalSignificance::from_008001(0b000_0100);
+        assert!(sig.is_significant_temperature());
+        assert!(sig.is_significant_humidity());
+        assert!(!sig.is_significant_wind());
+
+        let sig = VerticalSignificance::from_008001(0b000_0010);
+        assert!(sig.is_significant_wind());
+        assert!(!sig.is_significant_temperature());
+
+        // Bit 7, missing value, has no equivalent.
+        assert!(VerticalSignificance::from_008001(0b000_0001).is_empty());
+        assert_eq!(
+            VerticalSignificance::from_008001(0b110_0000).raw(),
+            0b11_0000_0000_0000_0000
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt::Display;
+
+use sounding_analysis::StationInfo;
+
+use crate::types::Structure;
+
+/** A WIGOS station identifier (301150), e.g. 0-20000-0-72776. */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WigosId {
+    series: u32,
+    issuer: u32,
+    issue_number: u32,
+    local_id: String,
+}
+
+impl WigosId {
+    /** WIGOS identifier series (001125). */
+    pub fn series(&self) -> u32 {
+        self.series
+    }
+
+    /** WIGOS issuer of identifier (001126). */
+    pub fn issuer(&self) -> u32 {
+        self.issuer
+    }
+
+    /** WIGOS issue number (001127). */
+    pub fn issue_number(&self) -> u32 {
+        self.issue_number
+    }
+
+    /** WIGOS local identifier (001128). */
+    pub fn local_id(&self) -> &str {
+        &self.local_id
+    }
+
+    /** The WMO block and station number for stations that were given a WIGOS identifier based on
+     * it, i.e. 0-20000-0-BBSSS.
+     */
+    pub fn wmo_station_number(&self) -> Option<i32> {
+        if self.series == 0
+            && self.issuer == 20000
+            && self.issue_number == 0
+            && self.local_id.len() == 5
+        {
+            self.local_id.parse().ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for WigosId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}-{}-{}-{}",
+            self.series, self.issuer, self.issue_number, self.local_id
+        )
+    }
+}
+
+/** The different ways a station may be identified, collected from a message. */
+#[derive(Debug, Default)]
+pub(super) struct StationIdentity {
+    block: Option<i32>,
+    station: Option<i32>,
+    call_sign: Option<String>,
+    wigos_series: Option<u32>,
+    wigos_issuer: Option<u32>,
+    wigos_issue_number: Option<u32>,
+    wigos_local_id: Option<String>,
+}
+
+impl StationIdentity {
+    /** Collect identification elements from these structures and any nested groups. */
+    pub(super) fn extract<'a>(&mut self, items: impl IntoIterator<Item = &'a Structure>) {
+        for structure in items {
+            let el = match structure {
+                Structure::Element(el) => el,
+                Structure::Group(grp) => {
+                    self.extract(grp.items());
+                    continue;
+                }
+                Structure::Replication(_) => continue,
+            };
+
+            match el.code() {
+                "001001" => self.block = el.get_i32_val(),
+                "001002" => self.station = el.get_i32_val(),
+                // Ship or mobile land station identifier, or the aircraft flight number for dropsondes.
+                "001011" | "001006" => {
+                    self.call_sign = el
+                        .get_str_val()
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                }
+                "001125" => self.wigos_series = el.get_u32_val(),
+                "001126" => self.wigos_issuer = el.get_u32_val(),
+                "001127" => self.wigos_issue_number = el.get_u32_val(),
+                "001128" => {
+                    self.wigos_local_id = el
+                        .get_str_val()
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub(super) fn wigos_id(&self) -> Option<WigosId> {
+        Some(WigosId {
+            series: self.wigos_series?,
+            issuer: self.wigos_issuer?,
+            issue_number: self.wigos_issue_number?,
+            local_id: self.wigos_local_id.clone()?,
+        })
+    }
+
+    /** The station number comes from the WMO block and station numbers, or a WIGOS identifier
+     * derived from them. The station id is the call sign, or failing that the WIGOS identifier.
+     */
+    pub(super) fn apply(&self, mut station: StationInfo) -> StationInfo {
+        let wigos = self.wigos_id();
+
+        let num = self
+            .block
+            .zip(self.station)
+            .map(|(block, station)| block * 1000 + station)
+            .or_else(|| wigos.as_ref().and_then(WigosId::wmo_station_number));
+        if let Some(num) = num {
+            station = station.with_station(num);
+        }
+
+        let id = self
+            .call_sign
+            .clone()
+            .or_else(|| wigos.map(|w| w.to_string()));
+        if id.is_some() {
+            station = station.with_station_id(id);
+        }
+
+        station
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Element, Value};
+
+    fn identity(values: Vec<(&'static str, Value)>) -> StationIdentity {
+        let items: Vec<Structure> = values
+            .into_iter()
+            .map(|(fxy, val)| Structure::Element(Element::new(val, "", "", fxy)))
+            .collect();
+
+        let mut identity = StationIdentity::default();
+        identity.extract(&items);
+        identity
+    }
+
+    fn wigos(local_id: &str) -> Vec<(&'static str, Value)> {
+        vec![
+            ("001125", Value::Numeric(0)),
+            ("001126", Value::Numeric(20000)),
+            ("001127", Value::Numeric(0)),
+            ("001128", Value::Str(format!("{:<16}", local_id))),
+        ]
+    }
+
+    #[test]
+    fn test_apply_wigos_fallback() {
+        // Without a block and station number, the WIGOS identifier gives both.
+        let station = identity(wigos("72776")).apply(StationInfo::new());
+        assert_eq!(station.station_num().into_option(), Some(72776));
+        assert_eq!(station.station_id(), Some("0-20000-0-72776"));
+
+        // A local identifier that isn't a WMO station number only gives the station id.
+        let station = identity(wigos("KMSO")).apply(StationInfo::new());
+        assert_eq!(station.station_num().into_option(), None);
+        assert_eq!(station.station_id(), Some("0-20000-0-KMSO"));
+
+        // The block and station number, and the call sign, come first.
+        let mut values = wigos("72776");
+        values.push(("001001", Value::Numeric(72)));
+        values.push(("001002", Value::Numeric(777)));
+        values.push(("001011", Value::Str("SHIP1   ".to_owned())));
+        let station = identity(values).apply(StationInfo::new());
+        assert_eq!(station.station_num().into_option(), Some(72777));
+        assert_eq!(station.station_id(), Some("SHIP1"));
+
+        // A partial WIGOS identifier isn't used.
+        let station = identity(wigos("72776").into_iter().skip(1).collect()).apply(StationInfo::new());
+        assert_eq!(station.station_num().into_option(), None);
+        assert_eq!(station.station_id(), None);
+    }
+}
@@ -0,0 +1,480 @@
+use std::error::Error;
+
+use chrono::{NaiveDateTime, TimeDelta};
+use metfor::{Celsius, HectoPascal, Kelvin, Knots, Meters, MetersPSec, Mm, WindSpdDir};
+use optional::Optioned;
+use sounding_analysis::{Sounding, StationInfo};
+
+use super::{CloudCover, LaunchTime, Moisture, StationIdentity, extract_station_location, extract_time_info};
+use crate::types::{BufrMessage, Element, Structure};
+
+/** Land SYNOP templates, the standard report (307080) and one-hour observations (307096). */
+pub(super) const SYNOP_TEMPLATES: [&str; 2] = ["307080", "307096"];
+
+/** Observations further apart than this aren't considered to be at the same time. */
+const MAX_ATTACH_MINUTES: i64 = 90;
+
+/** Precipitation over a period ending at the time of the observation. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Precipitation {
+    pub(super) hours: f64,
+    pub(super) amount: Mm,
+}
+
+impl Precipitation {
+    /** Length of the period in hours. */
+    pub fn hours(&self) -> f64 {
+        self.hours
+    }
+
+    /** Total precipitation, a trace is reported as -0.1 mm. */
+    pub fn amount(&self) -> Mm {
+        self.amount
+    }
+}
+
+/** A surface synoptic observation from a fixed land station. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SurfaceObservation {
+    pub(super) station: StationInfo,
+    pub(super) station_name: Option<String>,
+    pub(super) time: Option<NaiveDateTime>,
+    pub(super) station_pressure: Option<HectoPascal>,
+    pub(super) mslp: Option<HectoPascal>,
+    pub(super) pressure_change: Option<HectoPascal>,
+    pub(super) temperature: Option<Celsius>,
+    pub(super) dew_point: Option<Celsius>,
+    pub(super) relative_humidity: Option<f64>,
+    pub(super) wind: Option<WindSpdDir<Knots>>,
+    pub(super) gust: Option<Knots>,
+    pub(super) visibility: Option<Meters>,
+    pub(super) total_cloud_cover: Option<f64>,
+    pub(super) low_cloud: Option<f64>,
+    pub(super) mid_cloud: Option<f64>,
+    pub(super) high_cloud: Option<f64>,
+    pub(super) precipitation: Vec<Precipitation>,
+}
+
+impl SurfaceObservation {
+    /** Station number, identifier, location, and elevation. */
+    pub fn station_info(&self) -> &StationInfo {
+        &self.station
+    }
+
+    /** Station or site name (001015). */
+    pub fn station_name(&self) -> Option<&str> {
+        self.station_name.as_deref()
+    }
+
+    /** Time of the observation. */
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        self.time
+    }
+
+    /** Station pressure (010004). */
+    pub fn station_pressure(&self) -> Option<HectoPascal> {
+        self.station_pressure
+    }
+
+    /** Pressure reduced to mean sea level (010051). */
+    pub fn mslp(&self) -> Option<HectoPascal> {
+        self.mslp
+    }
+
+    /** 3-hour pressure change (010061). */
+    pub fn pressure_change(&self) -> Option<HectoPascal> {
+        self.pressure_change
+    }
+
+    /** Air temperature (012101). */
+    pub fn temperature(&self) -> Option<Celsius> {
+        self.temperature
+    }
+
+    /** Dew point, reported or derived from the relative humidity. */
+    pub fn dew_point(&self) -> Option<Celsius> {
+        self.dew_point
+    }
+
+    /** Relative humidity in percent (013003). */
+    pub fn relative_humidity(&self) -> Option<f64> {
+        self.relative_humidity
+    }
+
+    /** Mean wind speed and direction, usually over the last 10 minutes. */
+    pub fn wind(&self) -> Option<WindSpdDir<Knots>> {
+        self.wind
+    }
+
+    /** The first maximum wind gust reported (011041), usually over the last 10 minutes. */
+    pub fn gust(&self) -> Option<Knots> {
+        self.gust
+    }
+
+    /** Horizontal visibility (020001). */
+    pub fn visibility(&self) -> Option<Meters> {
+        self.visibility
+    }
+
+    /** Total cloud cover in percent (020010). */
+    pub fn total_cloud_cover(&self) -> Option<f64> {
+        self.total_cloud_cover
+    }
+
+    /** Low cloud fraction from 0 to 1, from the cloud amount (020011). */
+    pub fn low_cloud(&self) -> Option<f64> {
+        self.low_cloud
+    }
+
+    /** Middle cloud fraction from 0 to 1, from the cloud amount (020011). */
+    pub fn mid_cloud(&self) -> Option<f64> {
+        self.mid_cloud
+    }
+
+    /** High cloud fraction from 0 to 1, from the cloud amount (020011). */
+    pub fn high_cloud(&self) -> Option<f64> {
+        self.high_cloud
+    }
+
+    /** Precipitation for each period reported, in the order they were reported. */
+    pub fn precipitation(&self) -> &[Precipitation] {
+        &self.precipitation
+    }
+
+    /** Add the surface values to a sounding from the same station and time.
+     *
+     * The mean sea level pressure, the precipitation over the shortest period reported, and the
+     * surface wind replace those in the sounding. The station pressure, surface temperature, dew
+     * point, and cloud fractions are only filled in if the sounding doesn't have them. It is an
+     * error if the station numbers are different, or the times are more than 90 minutes apart.
+     */
+    pub fn attach_to(&self, snd: Sounding) -> Result<Sounding, Box<dyn Error>> {
+        let obs_num = self.station.station_num().into_option();
+        if let (Some(obs), Some(sonde)) = (obs_num, snd.station_info().station_num().into_option())
+            && obs != sonde
+        {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Surface observation is for station {}, not {}.", obs, sonde),
+            )));
+        }
+
+        if let (Some(obs), Some(sonde)) = (self.time, snd.valid_time())
+            && (obs - sonde).abs() > TimeDelta::minutes(MAX_ATTACH_MINUTES)
+        {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Surface observation at {} is too far from {}.", obs, sonde),
+            )));
+        }
+
+        let precipitation = self
+            .precipitation
+            .iter()
+            .filter(|p| p.hours > 0.0)
+            .min_by(|a, b| a.hours.total_cmp(&b.hours))
+            .map(|p| p.amount);
+
+        let fill = |current: Optioned<Celsius>, value: Option<Celsius>| current.into_option().or(value);
+
+        let mut snd = snd
+            .with_mslp(Optioned::<HectoPascal>::from(self.mslp))
+            .with_precipitation(Optioned::<Mm>::from(precipitation))
+            .with_sfc_wind(Optioned::<WindSpdDir<Knots>>::from(self.wind));
+
+        if snd.station_pressure().is_none() {
+            snd = snd.with_station_pressure(Optioned::<HectoPascal>::from(self.station_pressure));
+        }
+        let temperature = fill(snd.sfc_temperature(), self.temperature);
+        let dew_point = fill(snd.sfc_dew_point(), self.dew_point);
+        snd = snd
+            .with_sfc_temperature(Optioned::<Celsius>::from(temperature))
+            .with_sfc_dew_point(Optioned::<Celsius>::from(dew_point));
+
+        if snd.low_cloud().is_none() && snd.mid_cloud().is_none() && snd.high_cloud().is_none() {
+            snd = snd
+                .with_low_cloud(Optioned::<f64>::from(self.low_cloud))
+                .with_mid_cloud(Optioned::<f64>::from(self.mid_cloud))
+                .with_high_cloud(Optioned::<f64>::from(self.high_cloud));
+        }
+
+        Ok(snd)
+    }
+}
+
+/** Get the surface observations (307080, 307096) from every subset of a message. */
+pub fn surface_observations(bufr: &BufrMessage) -> Result<Vec<SurfaceObservation>, Box<dyn Error>> {
+    let mut observations = vec![];
+
+    for subset in bufr.subsets() {
+        for structure in subset {
+            if let Structure::Group(grp) = structure
+                && SYNOP_TEMPLATES.contains(&grp.code())
+            {
+                observations.push(observation_from_template(grp.items()));
+            }
+        }
+    }
+
+    if observations.is_empty() {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No supported surface observation template in message.",
+        )));
+    }
+
+    Ok(observations)
+}
+
+fn observation_from_template(items: &[Structure]) -> SurfaceObservation {
+    let mut obs = SurfaceObservation::default();
+    let mut identity = StationIdentity::default();
+    identity.extract(items);
+
+    let mut builder = ObservationBuilder::default();
+    for structure in items {
+        match structure {
+            // Surface station identification; time, horizontal and vertical coordinates
+            Structure::Group(grp) if grp.code() == "301090" => {
+                obs.station = extract_station_location(grp.items(), StationInfo::new());
+                let mut time = LaunchTime::default();
+                extract_time_info(grp.items(), &mut time);
+                obs.time = time.to_datetime();
+                builder.extract(grp.items(), &mut obs);
+            }
+            _ => builder.extract(std::slice::from_ref(structure), &mut obs),
+        }
+    }
+    obs.station = identity.apply(std::mem::take(&mut obs.station));
+
+    builder.build(obs)
+}
+
+/** Collects the first value of each element, in the order they were reported. */
+#[derive(Default)]
+struct ObservationBuilder {
+    moisture: Moisture,
+    dir: Option<f64>,
+    spd: Option<Knots>,
+    period: Option<f64>,
+    clouds: Option<CloudCover>,
+}
+
+impl ObservationBuilder {
+    fn extract(&mut self, items: &[Structure], obs: &mut SurfaceObservation) {
+        for structure in items {
+            let el = match structure {
+                Structure::Element(el) => el,
+                // General cloud information
+                Structure::Group(grp) if grp.code() == "302004" && self.clouds.is_none() => {
+                    self.clouds = Some(CloudCover::extract(grp.items()));
+                    self.extract(grp.items(), obs);
+                    continue;
+                }
+                Structure::Group(grp) => {
+                    self.extract(grp.items(), obs);
+                    continue;
+                }
+                Structure::Replication(rep) => {
+                    self.extract(rep.items(), obs);
+                    continue;
+                }
+            };
+
+            let kelvin = |el: &Element| el.get_f64_val().map(Kelvin).map(Celsius::from);
+            let hpa = |el: &Element| el.get_f64_val().map(|p| HectoPascal(p / 100.0));
+
+            match el.code() {
+                "001015" if obs.station_name.is_none() => {
+                    obs.station_name = el.get_str_val().map(str::trim).filter(|s| !s.is_empty()).map(String::from)
+                }
+                "010004" if obs.station_pressure.is_none() => obs.station_pressure = hpa(el),
+                "010051" if obs.mslp.is_none() => obs.mslp = hpa(el),
+                "010061" if obs.pressure_change.is_none() => obs.pressure_change = hpa(el),
+                "012101" if obs.temperature.is_none() => obs.temperature = kelvin(el),
+                "012103" if self.moisture.dew_point.is_none() => self.moisture.dew_point = kelvin(el),
+                "013003" if obs.relative_humidity.is_none() => {
+                    obs.relative_humidity = el.get_f64_val();
+                    self.moisture.relative_humidity = obs.relative_humidity;
+                }
+                "011001" if self.dir.is_none() => self.dir = el.get_f64_val(),
+                "011002" if self.spd.is_none() => self.spd = el.get_f64_val().map(MetersPSec).map(Knots::from),
+                "011041" if obs.gust.is_none() => obs.gust = el.get_f64_val().map(MetersPSec).map(Knots::from),
+                "020001" if obs.visibility.is_none() => obs.visibility = el.get_f64_val().map(Meters),
+                "020010" if obs.total_cloud_cover.is_none() => obs.total_cloud_cover = el.get_f64_val(),
+                // Time periods are reported as negative hours before the observation.
+                "004024" => self.period = el.get_i32_val().map(|h| -f64::from(h)),
+                "013011" => {
+                    if let Some((hours, amount)) = self.period.zip(el.get_f64_val()) {
+                        obs.precipitation.push(Precipitation { hours, amount: Mm(amount) });
+                    }
+                }
+                "013023" => {
+                    if let Some(amount) = el.get_f64_val() {
+                        obs.precipitation.push(Precipitation { hours: 24.0, amount: Mm(amount) });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn build(self, mut obs: SurfaceObservation) -> SurfaceObservation {
+        obs.wind = self
+            .dir
+            .zip(self.spd)
+            .map(|(direction, speed)| WindSpdDir { speed, direction });
+
+        obs.dew_point = self
+            .moisture
+            .dew_point(obs.temperature.into(), obs.station_pressure.into())
+            .map(|(td, _)| td);
+
+        if let Some(clouds) = self.clouds {
+            obs.low_cloud = clouds.low.into_option();
+            obs.mid_cloud = clouds.mid.into_option();
+            obs.high_cloud = clouds.high.into_option();
+        }
+
+        obs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Group, Value};
+    use chrono::NaiveDate;
+
+    fn element(fxy: &'static str, val: Value) -> Structure {
+        Structure::Element(Element::new(val, "", "", fxy))
+    }
+
+    fn group(fxy: &'static str, items: Vec<Structure>) -> Structure {
+        let mut grp = Group::new_with_capacity(items.len(), "", fxy);
+        for item in items {
+            grp.push(item);
+        }
+        Structure::Group(grp)
+    }
+
+    fn noon() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2017, 8, 31).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    fn observation() -> SurfaceObservation {
+        let items = vec![
+            group(
+                "301090",
+                vec![
+                    element("001001", Value::Numeric(72)),
+                    element("001002", Value::Numeric(776)),
+                    element("001015", Value::Str("MISSOULA ".to_owned())),
+                    element("004001", Value::Numeric(2017)),
+                    element("004002", Value::Numeric(8)),
+                    element("004003", Value::Numeric(31)),
+                    element("004004", Value::Numeric(12)),
+                    element("004005", Value::Numeric(0)),
+                    element("005001", Value::Float(46.92)),
+                    element("006001", Value::Float(-114.09)),
+                    element("007030", Value::Float(972.0)),
+                ],
+            ),
+            element("010004", Value::Float(90_520.0)),
+            element("010051", Value::Float(101_340.0)),
+            element("012101", Value::Float(300.15)),
+            element("012103", Value::Float(275.15)),
+            // Only the first value is used.
+            element("012101", Value::Float(280.15)),
+            group(
+                "302004",
+                vec![
+                    element("020010", Value::Float(50.0)),
+                    element("008002", Value::Code(7)),
+                    element("020011", Value::Code(3)),
+                ],
+            ),
+            element("004024", Value::Numeric(-6)),
+            element("013011", Value::Float(2.5)),
+            element("004024", Value::Numeric(-1)),
+            element("013011", Value::Float(0.5)),
+            element("013023", Value::Float(4.2)),
+            element("011001", Value::Float(270.0)),
+            element("011002", Value::Float(5.0)),
+        ];
+
+        observation_from_template(&items)
+    }
+
+    #[test]
+    fn test_observation_from_template() {
+        let obs = observation();
+
+        assert_eq!(obs.station_info().station_num().into_option(), Some(72776));
+        assert_eq!(obs.station_info().location(), Some((46.92, -114.09)));
+        assert_eq!(obs.station_name(), Some("MISSOULA"));
+        assert_eq!(obs.time(), Some(noon()));
+        assert_eq!(obs.station_pressure(), Some(HectoPascal(905.2)));
+        assert_eq!(obs.mslp(), Some(HectoPascal(1013.4)));
+        assert!(obs.temperature().is_some_and(|t| (t.0 - 27.0).abs() < 1e-9));
+        assert!(obs.dew_point().is_some_and(|td| (td.0 - 2.0).abs() < 1e-9));
+        assert!(obs.wind().is_some_and(|w| w.direction == 270.0 && w.speed == Knots::from(MetersPSec(5.0))));
+        assert_eq!(obs.total_cloud_cover(), Some(50.0));
+        assert_eq!(obs.low_cloud(), Some(3.0 / 8.0));
+        assert_eq!(obs.mid_cloud(), None);
+
+        // The periods are negated to hours before the observation.
+        let precipitation: Vec<(f64, Mm)> = obs.precipitation().iter().map(|p| (p.hours(), p.amount())).collect();
+        assert_eq!(precipitation, vec![(6.0, Mm(2.5)), (1.0, Mm(0.5)), (24.0, Mm(4.2))]);
+    }
+
+    #[test]
+    fn test_attach_to() {
+        let obs = observation();
+        let sounding = || {
+            Sounding::new()
+                .with_station_info(StationInfo::new().with_station(72776))
+                .with_valid_time(noon())
+        };
+
+        let snd = obs
+            .attach_to(
+                sounding()
+                    .with_station_pressure(HectoPascal(904.0))
+                    .with_mslp(HectoPascal(1000.0))
+                    .with_sfc_temperature(Celsius(25.0)),
+            )
+            .unwrap();
+
+        // Replaced
+        assert_eq!(snd.mslp().into_option(), Some(HectoPascal(1013.4)));
+        assert_eq!(snd.precipitation().into_option(), Some(Mm(0.5)));
+        assert_eq!(snd.sfc_wind().into_option(), obs.wind());
+        // Only filled in if missing
+        assert_eq!(snd.station_pressure().into_option(), Some(HectoPascal(904.0)));
+        assert_eq!(snd.sfc_temperature().into_option(), Some(Celsius(25.0)));
+        assert_eq!(snd.sfc_dew_point().into_option(), obs.dew_point());
+        assert_eq!(snd.low_cloud().into_option(), Some(3.0 / 8.0));
+
+        let snd = obs.attach_to(sounding().with_high_cloud(0.5)).unwrap();
+        assert_eq!(snd.station_pressure().into_option(), Some(HectoPascal(905.2)));
+        assert_eq!(snd.sfc_temperature().into_option(), obs.temperature());
+        assert_eq!(snd.low_cloud().into_option(), None);
+        assert_eq!(snd.high_cloud().into_option(), Some(0.5));
+    }
+
+    #[test]
+    fn test_attach_to_mismatch() {
+        let obs = observation();
+
+        let other_station = Sounding::new().with_station_info(StationInfo::new().with_station(72777));
+        assert!(obs.attach_to(other_station).is_err());
+
+        let at = |minutes| Sounding::new().with_valid_time(noon() + TimeDelta::minutes(minutes));
+        assert!(obs.attach_to(at(-90)).is_ok());
+        assert!(obs.attach_to(at(91)).is_err());
+
+        // Without a station number or time there is nothing to check.
+        assert!(obs.attach_to(Sounding::new()).is_ok());
+    }
+}
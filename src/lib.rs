@@ -4,6 +4,7 @@ use std::{
 };
 
 mod bit_buffer;
+mod reader;
 mod section0;
 mod section1;
 mod section2;
@@ -13,8 +14,25 @@ mod section5;
 mod tables;
 mod types;
 mod easy_api;
+#[cfg(test)]
+mod test_util;
 
-pub use easy_api::load_309052_sounding;
+pub use easy_api::{
+    AircraftObservation, AircraftProfile, CodeValue, CycloneTrack, ElementMapping, FlightPhase,
+    LevelInfo, MessageKind, MoistureSource, OccultationLevel, OccultationProfile, OceanLevel,
+    OceanProfile, Precipitation, ProfileMapping, ProfilerGate, ProfilerReport, QcChange,
+    RadiosondeMetadata, RadiosondeReport, RadiosondeTemplate, SurfaceObservation, TempPart,
+    TrackPoint, VerticalSignificance, WigosId, WindShear, aircraft_observations, aircraft_profiles,
+    classify_message, cyclone_tracks, forecast_soundings, load_309052_sounding,
+    load_309052_sounding_from_reader, load_309052_sounding_from_slice, load_forecast_soundings,
+    load_report, load_report_from_reader, load_report_from_slice, load_sounding,
+    load_sounding_from_reader, load_sounding_from_slice, merge_temp_parts, occultation_profiles,
+    ocean_profiles, profiler_reports, report_from_message, retrieved_soundings,
+    sounding_from_message, surface_observations,
+};
+
+pub use crate::reader::{BufrReader, BufrSliceReader, WmoHeader};
+pub use crate::tables::local::LocalTables;
 
 use crate::types::BufrMessage;
 
@@ -22,7 +40,34 @@ pub const MAX_BUFR_TABLE_VERSION_SUPPORTED: u8 = 39;
 pub const MAX_BUFR_EDITION_SUPPORTED: u8 = 4;
 pub const MIN_BUFR_EDITION_SUPPORTED: u8 = 4;
 
-pub fn read_bufr_message(mut f: impl Read) -> Result<BufrMessage, Box<dyn Error>> {
+pub fn read_bufr_message(f: impl Read) -> Result<BufrMessage, Box<dyn Error>> {
+    read_bufr_message_with_tables(f, &LocalTables::new())
+}
+
+/** Read a message that may use local Table B and D entries, like those in the DX tables NCEP sends
+ * ahead of its data. See `LocalTables`.
+ */
+pub fn read_bufr_message_with_tables(f: impl Read, tables: &LocalTables) -> Result<BufrMessage, Box<dyn Error>> {
+    let message = read_message_bytes(f)?;
+    read_bufr_message_from_slice_with_tables(&message, tables)
+}
+
+/** Decode the message at the start of `bytes` in place, without copying it, e.g. from a memory
+ * mapped file. Anything after the length of the message in section 0 is ignored.
+ */
+pub fn read_bufr_message_from_slice(bytes: &[u8]) -> Result<BufrMessage, Box<dyn Error>> {
+    read_bufr_message_from_slice_with_tables(bytes, &LocalTables::new())
+}
+
+/** Decode the message at the start of `bytes` in place, see `read_bufr_message_from_slice` and
+ * `read_bufr_message_with_tables`.
+ */
+pub fn read_bufr_message_from_slice_with_tables(
+    bytes: &[u8],
+    tables: &LocalTables,
+) -> Result<BufrMessage, Box<dyn Error>> {
+    let mut f = frame_message(bytes)?;
+
     let mut builder = types::BufrMessageBuilder::new();
 
     section0::read_section_0(&mut f, &mut builder)?;
@@ -33,13 +78,114 @@ pub fn read_bufr_message(mut f: impl Read) -> Result<BufrMessage, Box<dyn Error>
     }
 
     let descriptors = section3::read_section_3(&mut f, &mut builder)?;
-    section4::read_section_4(&mut f, descriptors, &mut builder)?;
+    section4::read_section_4(&mut f, descriptors, tables, &mut builder)?;
     section5::read_section_5(&mut f)?;
 
     Ok(builder.build())
 }
 
-pub fn scan_to_bufr_start(mut f: impl Seek + Read) -> Result<Vec<u8>, Box<dyn Error>> {
+/** Read exactly the length of the message in section 0, so a message that fails to decode doesn't
+ * leave the input in the middle of it.
+ */
+fn read_message_bytes(mut f: impl Read) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut section_0 = [0; section0::SECTION_0_SIZE];
+    f.read_exact(&mut section_0)?;
+
+    let message_size = section0::read_message_size(section_0.as_slice())?
+        .ok_or_else(missing_length)?
+        .max(section0::SECTION_0_SIZE);
+
+    let mut message = vec![0; message_size];
+    message[..section0::SECTION_0_SIZE].copy_from_slice(&section_0);
+    f.read_exact(&mut message[section0::SECTION_0_SIZE..])?;
+
+    Ok(message)
+}
+
+fn missing_length() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "BUFR message length in section 0 is missing.")
+}
+
+/** The message at the start of `bytes`, checking that it has a "7777" at the end of the length in
+ * section 0.
+ */
+fn frame_message(bytes: &[u8]) -> Result<&[u8], Box<dyn Error>> {
+    let message_size = section0::read_message_size(bytes)?.ok_or_else(missing_length)?;
+    if message_size < section0::SECTION_0_SIZE + section5::SECTION_5_SIZE {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "BUFR message length in section 0 is too short.",
+        )));
+    }
+
+    let message = bytes.get(..message_size).ok_or(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "BUFR message is shorter than the length in section 0.",
+    ))?;
+
+    if !message.ends_with(b"7777") {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No 7777 at the end of the BUFR message length in section 0.",
+        )));
+    }
+
+    Ok(message)
+}
+
+/** Find the start of the next message in `bytes`, the slice version of `scan_to_bufr_start`. The
+ * bytes before it usually hold a WMO heading.
+ */
+pub fn find_bufr_start(bytes: &[u8]) -> Option<usize> {
+    find_message(bytes).map(|(start, _)| start)
+}
+
+/** The offset and bytes of the next message in `bytes`. */
+pub(crate) fn find_message(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    bytes
+        .windows(4)
+        .enumerate()
+        .filter(|(_, window)| window == b"BUFR")
+        .find_map(|(start, _)| frame_message(&bytes[start..]).ok().map(|message| (start, message)))
+}
+
+/** Check for a whole message at `position`, one that has "7777" at the end of the length in
+ * section 0, without moving past it.
+ */
+fn is_message_at(mut f: impl Seek + Read, position: u64) -> Result<bool, Box<dyn Error>> {
+    f.seek(std::io::SeekFrom::Start(position))?;
+    let message_size = match section0::read_message_size(&mut f) {
+        Ok(Some(size)) if size >= section0::SECTION_0_SIZE + section5::SECTION_5_SIZE => size,
+        _ => return Ok(false),
+    };
+
+    let end = position + (message_size - section5::SECTION_5_SIZE) as u64;
+    f.seek(std::io::SeekFrom::Start(end))?;
+
+    let mut section_end: [u8; 4] = [0; 4];
+    let is_message = f.read_exact(&mut section_end).is_ok() && &section_end == b"7777";
+
+    f.seek(std::io::SeekFrom::Start(position))?;
+    Ok(is_message)
+}
+
+/** Move to the start of the next message, returning the bytes skipped over, which usually hold a
+ * WMO heading. See `WmoHeader` and `BufrReader`.
+ *
+ * A "BUFR" only starts a message if there is a "7777" at the end of the length in section 0, so
+ * "BUFR" in other data and messages that are cut short or have a corrupt length are skipped.
+ */
+pub fn scan_to_bufr_start(f: impl Seek + Read) -> Result<Vec<u8>, Box<dyn Error>> {
+    scan_for_bufr_start(f)?.ok_or_else(|| {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "No more bufr messages in file.",
+        )) as Box<dyn Error>
+    })
+}
+
+/** Like `scan_to_bufr_start`, but `None` at the end of the input. */
+pub(crate) fn scan_for_bufr_start(mut f: impl Seek + Read) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
     let mut header: Vec<u8> = vec![];
     let mut position: u64 = f.stream_position()?;
 
@@ -48,17 +194,20 @@ pub fn scan_to_bufr_start(mut f: impl Seek + Read) -> Result<Vec<u8>, Box<dyn Er
         let num_read = f.read(&mut buffer)?;
 
         if num_read == 0 {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "No more bufr messages in file.",
-            )));
+            return Ok(None);
         }
 
         let mut scan_start = 0;
-        if buffer[0] == 'B' as u8 && buffer[1] == 'U' as u8 && buffer[2] == 'F' as u8 && buffer[3] == 'R' as u8 {
-            f.seek(std::io::SeekFrom::Start(position))?;
-            return Ok(header);
-        } else if buffer[0] == 'B' as u8 {
+        if buffer[..num_read].starts_with(b"BUFR") {
+            if is_message_at(&mut f, position)? {
+                return Ok(Some(header));
+            }
+
+            // Keep looking after it.
+            f.seek(std::io::SeekFrom::Start(position + num_read as u64))?;
+        }
+
+        if buffer[0] == 'B' as u8 {
             scan_start = 1;
             position += 1;
             header.push(buffer[0]);
@@ -102,7 +251,7 @@ fn read_2_octet_u16(mut f: impl Read) -> Result<Option<u16>, Box<dyn Error>> {
 }
 
 fn read_3_octet_usize(mut f: impl Read) -> Result<Option<usize>, Box<dyn Error>> {
-    const MISSING: u64 = 0o0_0_0_0_0_7_7_7;
+    const MISSING: u64 = 0xFF_FFFF;
 
     let mut message_size: [u8; 3] = [0; 3];
     f.read_exact(&mut message_size)?;
@@ -124,3 +273,92 @@ fn read_3_octet_usize(mut f: impl Read) -> Result<Option<usize>, Box<dyn Error>>
 
     Ok(message_size)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** A message that is only a section 0 with this length, padding, and the 7777. */
+    fn fake_message(len: usize) -> Vec<u8> {
+        let mut message = b"BUFR".to_vec();
+        message.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+        message.push(4);
+        message.resize(len - 4, 0);
+        message.extend_from_slice(b"7777");
+        message
+    }
+
+    #[test]
+    fn test_read_3_octet_usize() {
+        assert_eq!(read_3_octet_usize(&[0x00, 0x01, 0xFF][..]).unwrap(), Some(511));
+        assert_eq!(read_3_octet_usize(&[0x01, 0x90, 0xDF][..]).unwrap(), Some(102_623));
+        assert_eq!(read_3_octet_usize(&[0xFF, 0xFF, 0xFF][..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_frame_message() {
+        let message = fake_message(511);
+        let mut bytes = message.clone();
+        bytes.extend_from_slice(b"trailing junk");
+        assert_eq!(frame_message(&bytes).unwrap(), &message[..]);
+
+        // Cut short
+        assert!(frame_message(&message[..510]).is_err());
+
+        // Missing length
+        let mut missing = message.clone();
+        missing[4..7].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        let err = frame_message(&missing).unwrap_err();
+        assert!(err.to_string().contains("missing"), "{}", err);
+
+        // Length that doesn't end at the 7777
+        let mut wrong_length = message.clone();
+        wrong_length[6] = 0xFE;
+        assert!(frame_message(&wrong_length).is_err());
+    }
+
+    #[test]
+    fn test_find_message() {
+        let message = fake_message(511);
+
+        let mut bytes = b"IUSN01 KWBC 311500\r\nstray BUFR in the heading\r\n".to_vec();
+        let start = bytes.len();
+        bytes.extend_from_slice(&message);
+
+        assert_eq!(find_bufr_start(&bytes), Some(start));
+        assert_eq!(find_message(&bytes).map(|(_, m)| m.len()), Some(511));
+        assert_eq!(find_bufr_start(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_scan_to_bufr_start() {
+        let message = fake_message(511);
+
+        let mut bytes = b"BUFR junk\r\n".to_vec();
+        bytes.extend_from_slice(&message);
+
+        let mut f = std::io::Cursor::new(&bytes);
+        let header = scan_to_bufr_start(&mut f).unwrap();
+        assert_eq!(header, b"BUFR junk\r\n");
+        assert_eq!(f.position(), 11);
+
+        let message = read_message_bytes(&mut f).unwrap();
+        assert_eq!(message.len(), 511);
+        assert!(scan_for_bufr_start(&mut f).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_test_data() {
+        let bytes = include_bytes!("../test-data/2017083115.bufr");
+
+        assert_eq!(find_bufr_start(bytes), Some(20));
+        let from_slice = read_bufr_message_from_slice(&bytes[20..]).unwrap();
+
+        let mut f = std::io::Cursor::new(&bytes[..]);
+        scan_to_bufr_start(&mut f).unwrap();
+        let from_reader = read_bufr_message(&mut f).unwrap();
+
+        assert_eq!(from_slice.descriptors(), from_reader.descriptors());
+        assert_eq!(from_slice.subsets().len(), from_reader.subsets().len());
+    }
+}
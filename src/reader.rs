@@ -0,0 +1,375 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{
+    find_message, read_bufr_message, read_bufr_message_from_slice, scan_for_bufr_start,
+    types::BufrMessage,
+};
+
+/** Start of heading, the first character of a GTS bulletin. */
+const SOH: u8 = 0x01;
+
+/** End of text, the last character of a GTS bulletin. */
+const ETX: u8 = 0x03;
+
+/** How far past the end of a message to look for the end of the bulletin. */
+const MAX_TRAILER_LEN: usize = 8;
+
+/** The abbreviated heading of the GTS bulletin a message came in, e.g. "IUSN01 KWBC 311500". See
+ * WMO-No. 386, the Manual on the GTS.
+ *
+ * Everything is optional, files from other sources often don't have a heading at all.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WmoHeader {
+    ttaaii: Option<String>,
+    cccc: Option<String>,
+    yygggg: Option<String>,
+    bbb: Option<String>,
+    sequence_number: Option<u32>,
+    soh: bool,
+    etx: bool,
+    raw: Vec<u8>,
+}
+
+impl WmoHeader {
+    /** Parse the bytes in front of a message. Only the last bulletin in them is used, anything
+     * before it, like the end of the previous bulletin, is ignored.
+     */
+    pub fn parse(raw: &[u8]) -> Self {
+        let mut header = WmoHeader {
+            raw: raw.to_vec(),
+            ..WmoHeader::default()
+        };
+
+        let text = match raw.iter().rposition(|&b| b == SOH) {
+            Some(start) => {
+                header.soh = true;
+                &raw[(start + 1)..]
+            }
+            None => raw,
+        };
+
+        let text = String::from_utf8_lossy(text);
+        let lines: Vec<&str> = text
+            .split(['\r', '\n'])
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        // The heading is the last line before the message, but there may be other junk before it.
+        let heading = lines.iter().rposition(|line| Self::parse_heading(line, &mut header));
+
+        let before_heading = heading.unwrap_or(lines.len());
+        if before_heading > 0 {
+            let line = lines[before_heading - 1];
+            if (3..=5).contains(&line.len()) && line.bytes().all(|b| b.is_ascii_digit()) {
+                header.sequence_number = line.parse().ok();
+            }
+        }
+
+        header
+    }
+
+    /** Parse "TTAAii CCCC YYGGgg [BBB]", only setting the fields if the whole line is valid. */
+    fn parse_heading(line: &str, header: &mut WmoHeader) -> bool {
+        let groups: Vec<&str> = line.split_whitespace().collect();
+        if groups.len() != 3 && groups.len() != 4 {
+            return false;
+        }
+
+        let is_ttaaii = |s: &str| {
+            s.len() == 6
+                && s.bytes().take(4).all(|b| b.is_ascii_uppercase())
+                && s.bytes().skip(4).all(|b| b.is_ascii_digit())
+        };
+        let is_cccc = |s: &str| s.len() == 4 && s.bytes().all(|b| b.is_ascii_uppercase());
+        let is_yygggg = |s: &str| s.len() == 6 && s.bytes().all(|b| b.is_ascii_digit());
+        let is_bbb = |s: &str| s.len() == 3 && s.bytes().all(|b| b.is_ascii_uppercase());
+
+        if !is_ttaaii(groups[0]) || !is_cccc(groups[1]) || !is_yygggg(groups[2]) {
+            return false;
+        }
+        if groups.len() == 4 && !is_bbb(groups[3]) {
+            return false;
+        }
+
+        header.ttaaii = Some(groups[0].to_owned());
+        header.cccc = Some(groups[1].to_owned());
+        header.yygggg = Some(groups[2].to_owned());
+        header.bbb = groups.get(3).map(|&s| s.to_owned());
+        true
+    }
+
+    /** Data type and designator, area, and number (TTAAii), e.g. "IUSN01". */
+    pub fn ttaaii(&self) -> Option<&str> {
+        self.ttaaii.as_deref()
+    }
+
+    /** ICAO location indicator of the center that compiled the bulletin (CCCC), e.g. "KWBC". */
+    pub fn cccc(&self) -> Option<&str> {
+        self.cccc.as_deref()
+    }
+
+    /** Day of the month, hour, and minute of the bulletin (YYGGgg), e.g. "311500". */
+    pub fn yygggg(&self) -> Option<&str> {
+        self.yygggg.as_deref()
+    }
+
+    /** Day of the month from YYGGgg. */
+    pub fn day(&self) -> Option<u32> {
+        self.yygggg_part(0)
+    }
+
+    /** Hour from YYGGgg. */
+    pub fn hour(&self) -> Option<u32> {
+        self.yygggg_part(2)
+    }
+
+    /** Minute from YYGGgg. */
+    pub fn minute(&self) -> Option<u32> {
+        self.yygggg_part(4)
+    }
+
+    fn yygggg_part(&self, start: usize) -> Option<u32> {
+        self.yygggg.as_ref()?.get(start..(start + 2))?.parse().ok()
+    }
+
+    /** Indicator for a delayed (RRx), corrected (CCx), amended (AAx), or segmented (Pxx) bulletin. */
+    pub fn bbb(&self) -> Option<&str> {
+        self.bbb.as_deref()
+    }
+
+    /** Channel sequence number from the line before the heading. */
+    pub fn sequence_number(&self) -> Option<u32> {
+        self.sequence_number
+    }
+
+    /** Whether the bulletin started with SOH and ended with ETX after the message. */
+    pub fn is_framed(&self) -> bool {
+        self.soh && self.etx
+    }
+
+    /** Whether the bulletin started with SOH. */
+    pub fn has_soh(&self) -> bool {
+        self.soh
+    }
+
+    /** Whether the message was followed by ETX. */
+    pub fn has_etx(&self) -> bool {
+        self.etx
+    }
+
+    /** All the bytes between the previous message and this one. */
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+impl Display for WmoHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let groups = [&self.ttaaii, &self.cccc, &self.yygggg, &self.bbb];
+        let heading: Vec<&str> = groups.iter().filter_map(|g| g.as_deref()).collect();
+
+        if heading.is_empty() {
+            write!(f, "No WMO heading")
+        } else {
+            write!(f, "{}", heading.join(" "))
+        }
+    }
+}
+
+/** Iterate over the messages in a file or stream, with the heading of the bulletin each one came in.
+ *
+ * A message that fails to decode is returned as an error, and reading continues with the next
+ * message. The iterator ends at the end of the input, or if the input itself can't be read.
+ */
+pub struct BufrReader<R> {
+    f: R,
+    done: bool,
+}
+
+impl<R: Read + Seek> BufrReader<R> {
+    /** Read messages from `f`, which should be buffered, like a `BufReader` of a file. */
+    pub fn new(f: R) -> Self {
+        BufrReader { f, done: false }
+    }
+
+    /** Get the underlying reader back. */
+    pub fn into_inner(self) -> R {
+        self.f
+    }
+
+    /** Skip an ETX after a message, and any line endings before it. */
+    fn read_etx(&mut self) -> Result<bool, Box<dyn Error>> {
+        let position = self.f.stream_position()?;
+
+        let mut buffer: Vec<u8> = Vec::with_capacity(MAX_TRAILER_LEN);
+        (&mut self.f).take(MAX_TRAILER_LEN as u64).read_to_end(&mut buffer)?;
+
+        let etx = trailer_len(&buffer);
+        self.f.seek(SeekFrom::Start(position + etx.unwrap_or(0) as u64))?;
+
+        Ok(etx.is_some())
+    }
+}
+
+/** How many bytes to skip to get past an ETX at the start of `bytes`, if there is one. */
+fn trailer_len(bytes: &[u8]) -> Option<usize> {
+    let bytes = &bytes[..bytes.len().min(MAX_TRAILER_LEN)];
+
+    bytes
+        .iter()
+        .position(|b| !matches!(b, b'\r' | b'\n' | b' '))
+        .filter(|&i| bytes[i] == ETX)
+        .map(|i| i + 1)
+}
+
+impl<R: Read + Seek> Iterator for BufrReader<R> {
+    type Item = (WmoHeader, Result<BufrMessage, Box<dyn Error>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let raw = match scan_for_bufr_start(&mut self.f) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(err) => {
+                self.done = true;
+                return Some((WmoHeader::default(), Err(err)));
+            }
+        };
+
+        let mut header = WmoHeader::parse(&raw);
+        let bufr = read_bufr_message(&mut self.f);
+
+        if bufr.is_ok() {
+            // A read error here will show up when looking for the next message.
+            header.etx = self.read_etx().unwrap_or(false);
+        }
+
+        Some((header, bufr))
+    }
+}
+
+/** Iterate over the messages in a byte slice, like `BufrReader` but decoding each message in place
+ * without copying it, e.g. from a memory mapped file.
+ */
+pub struct BufrSliceReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BufrSliceReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BufrSliceReader { bytes, position: 0 }
+    }
+
+    /** Offset in the slice of the end of the last message returned. */
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl Iterator for BufrSliceReader<'_> {
+    type Item = (WmoHeader, Result<BufrMessage, Box<dyn Error>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.bytes[self.position..];
+
+        let (start, message) = match find_message(rest) {
+            Some(found) => found,
+            None => {
+                self.position = self.bytes.len();
+                return None;
+            }
+        };
+
+        let mut header = WmoHeader::parse(&rest[..start]);
+        let bufr = read_bufr_message_from_slice(message);
+        let end = start + message.len();
+
+        let etx = if bufr.is_ok() { trailer_len(&rest[end..]) } else { None };
+        header.etx = etx.is_some();
+        self.position += end + etx.unwrap_or(0);
+
+        Some((header, bufr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_heading() {
+        let header = WmoHeader::parse(b"\x01\r\r\n123\r\r\nIUSN01 KWBC 311500\r\r\n");
+        assert_eq!(header.ttaaii(), Some("IUSN01"));
+        assert_eq!(header.cccc(), Some("KWBC"));
+        assert_eq!(header.yygggg(), Some("311500"));
+        assert_eq!((header.day(), header.hour(), header.minute()), (Some(31), Some(15), Some(0)));
+        assert_eq!(header.bbb(), None);
+        assert_eq!(header.sequence_number(), Some(123));
+        assert!(header.has_soh());
+        assert!(!header.is_framed());
+        assert_eq!(header.to_string(), "IUSN01 KWBC 311500");
+
+        let header = WmoHeader::parse(b"\x01\r\r\n12345\r\r\nIUSD10 EDZW 010000 RRA\r\r\n");
+        assert_eq!(header.bbb(), Some("RRA"));
+        assert_eq!(header.sequence_number(), Some(12345));
+        assert_eq!(header.to_string(), "IUSD10 EDZW 010000 RRA");
+    }
+
+    #[test]
+    fn test_parse_sequence_number() {
+        let header = WmoHeader::parse(b"\x01\r\r\n12\r\r\nIUSN01 KWBC 311500\r\r\n");
+        assert_eq!(header.sequence_number(), None);
+
+        let header = WmoHeader::parse(b"\x01\r\r\n123456\r\r\nIUSN01 KWBC 311500\r\r\n");
+        assert_eq!(header.sequence_number(), None);
+
+        let header = WmoHeader::parse(b"\x01\r\r\nIUSN01 KWBC 311500\r\r\n");
+        assert_eq!(header.sequence_number(), None);
+        assert_eq!(header.ttaaii(), Some("IUSN01"));
+    }
+
+    #[test]
+    fn test_parse_without_soh() {
+        let header = WmoHeader::parse(b"IUSN01 KWBC 311500\n");
+        assert!(!header.has_soh());
+        assert_eq!(header.ttaaii(), Some("IUSN01"));
+
+        // Only the last bulletin counts, junk after the heading doesn't.
+        let header = WmoHeader::parse(b"\x01\r\r\n001\r\r\nIUSN01 KWBC 311500\r\r\n\x03\x01\r\r\njunk\r\r\n");
+        assert!(header.has_soh());
+        assert_eq!(header.ttaaii(), None);
+        assert_eq!(header.sequence_number(), None);
+
+        let header = WmoHeader::parse(b"");
+        assert_eq!(header, WmoHeader::default());
+        assert_eq!(header.to_string(), "No WMO heading");
+
+        let header = WmoHeader::parse(b"iusn01 KWBC 311500\n");
+        assert_eq!(header.ttaaii(), None);
+        assert_eq!(header.raw(), b"iusn01 KWBC 311500\n");
+    }
+
+    #[test]
+    fn test_trailer_len() {
+        assert_eq!(trailer_len(b"\x03"), Some(1));
+        assert_eq!(trailer_len(b"\r\r\n\x03\x01"), Some(4));
+        assert_eq!(trailer_len(b"  \r\r\n\r\n\x03"), Some(8));
+        assert_eq!(trailer_len(b"  \r\r\n\r\n\r\x03"), None);
+        assert_eq!(trailer_len(b"\r\r\nBUFR"), None);
+        assert_eq!(trailer_len(b"\r\r\n"), None);
+        assert_eq!(trailer_len(b""), None);
+    }
+}
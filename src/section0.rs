@@ -1,10 +1,26 @@
 use crate::{read_1_octet_u8, read_3_octet_usize, types::BufrMessageBuilder};
 use std::{error::Error, io::Read};
 
+/** Length of section 0, "BUFR" followed by the message length and edition number. */
+pub(super) const SECTION_0_SIZE: usize = 8;
+
 pub(super) fn read_section_0(
     mut f: impl Read,
     builder: &mut BufrMessageBuilder,
 ) -> Result<(), Box<dyn Error>> {
+    read_message_size(&mut f)?;
+    let bufr_version = read_1_octet_u8(&mut f)?
+        .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "BUFR Version Missing"))?;
+
+    builder.bufr_version(bufr_version);
+
+    Ok(())
+}
+
+/** Read the magic value and the length of the whole message, including sections 0 and 5, which is
+ * `None` if it is missing.
+ */
+pub(super) fn read_message_size(mut f: impl Read) -> Result<Option<usize>, Box<dyn Error>> {
     let mut bufr_name: [u8; 4] = [0; 4];
     f.read_exact(&mut bufr_name)?;
     let bufr_name = std::str::from_utf8(&bufr_name)?;
@@ -13,11 +29,5 @@ pub(super) fn read_section_0(
         return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid BUFR Magic Value")));
     }
 
-    let _message_size = read_3_octet_usize(&mut f)?;
-    let bufr_version = read_1_octet_u8(&mut f)?
-        .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "BUFR Version Missing"))?;
-
-    builder.bufr_version(bufr_version);
-
-    Ok(())
+    read_3_octet_usize(&mut f)
 }
@@ -1,6 +1,9 @@
 use crate::{read_1_octet_u8, read_2_octet_u16, read_3_octet_usize, types::BufrMessageBuilder};
 use std::{error::Error, io::Read};
 
+/** Master table for oceanographic data, maintained by the IOC of UNESCO. */
+pub(super) const OCEANOGRAPHIC_MASTER_TABLE: u8 = 10;
+
 #[rustfmt::skip]
 pub(super) fn read_section_1(mut f: impl Read, builder: &mut BufrMessageBuilder) -> Result<bool, Box<dyn Error>> {
     let section_size = read_3_octet_usize(&mut f)?                                                           // octets 1-3
@@ -40,7 +43,7 @@ pub(super) fn read_section_1(mut f: impl Read, builder: &mut BufrMessageBuilder)
     let mut extra_data = vec![];
     f.take(section_size as u64 - 22).read_to_end(&mut extra_data)?;
 
-    if master_table != 0 && master_table != 10 {
+    if master_table != 0 && master_table != OCEANOGRAPHIC_MASTER_TABLE {
         return Err(Box::new(std::io::Error::other("Non-meteorological / non-oceanographic data!")));
     }
 
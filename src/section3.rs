@@ -48,30 +48,15 @@ impl Descriptor {
         let x = fx & 0b0011_1111u8;
         let y = (desc & 0b0000_0000_1111_1111u16) as u8;
 
+        // Element Descriptor, replication, operator (Table C), and sequence (Table D) descriptors
+        // are all handled while reading section 4. Class 0 is only used by messages that define
+        // BUFR tables.
         if f == 0 {
             debug_assert!(x < 64); // only have 6 bits to work with!
 
-            // Element Descriptor
-            if x < 1 || x == 9 {
+            if x == 9 {
                 return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid Table B class")));
             }
-        } else if f == 1 {
-            // Replication Descriptor
-            if y == 0 {
-                return Err(
-                    Box::new(
-                        std::io::Error::new(
-                            std::io::ErrorKind::InvalidInput,
-                            "Delayed Replication Not Implemented",
-                        )
-                    )
-                );
-            }
-        } else if f == 2 {
-            // Operator Descriptor
-                return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Operators not implemented.")));
-        } else {
-            // Sequence (or Group) Descriptor - must use table D
         }
 
         Ok(Descriptor { f, x, y })
@@ -125,9 +110,6 @@ pub(super) fn read_section_3(mut f: impl Read, builder: &mut BufrMessageBuilder)
         return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Reserved Bits Required To Be 0")));
     }
 
-    // FIXME: Learn to handle compressed data
-    assert!(!compressed_data, "Compressed data handling not implemented.");
-
     builder.observed_data(observed_data).compressed_data(compressed_data);
 
     let num_descriptors = (section_size - 7) / 2;
@@ -145,5 +127,7 @@ pub(super) fn read_section_3(mut f: impl Read, builder: &mut BufrMessageBuilder)
         let _v = read_1_octet_u8(&mut f)?;
     }
 
+    builder.descriptors(descriptors.iter().map(Descriptor::string_form).collect());
+
     Ok(descriptors)
 }
@@ -2,43 +2,192 @@ use crate::{
     bit_buffer::BitBuffer,
     read_1_octet_u8, read_3_octet_usize,
     section3::Descriptor,
-    tables::{table_b, table_d},
+    tables::local::LocalTables,
     types::{BufrMessageBuilder, Element, Group, Replication, Structure, Value},
 };
-use std::{error::Error, io::Read};
+use std::error::Error;
+
+/** Table C operators that change how the elements following them are decoded.
+ *
+ * Once defined an operator stays in effect until it is cancelled, even past the end of the
+ * sequence it was defined in.
+ */
+#[derive(Debug, Default)]
+struct Operators {
+    // 201YYY
+    width_change: i32,
+    // 202YYY
+    scale_change: i32,
+    // 204YYY, the widths of the (nested) associated fields in bits
+    associated_fields: Vec<usize>,
+    // 207YYY
+    increase_scale_ref_width: i32,
+    // 208YYY, in bits
+    char_width: Option<usize>,
+}
+
+impl Operators {
+    fn apply(&mut self, desc: &Descriptor) -> Result<(), Box<dyn Error>> {
+        debug_assert_eq!(desc.f_value(), 2, "Not an operator descriptor, f={}", desc.f_value());
+
+        let y = i32::from(desc.y_value());
+        match desc.x_value() {
+            1 => self.width_change = if y == 0 { 0 } else { y - 128 },
+            2 => self.scale_change = if y == 0 { 0 } else { y - 128 },
+            4 => {
+                if y == 0 {
+                    self.associated_fields.pop();
+                } else {
+                    self.associated_fields.push(y as usize);
+                }
+            }
+            7 => self.increase_scale_ref_width = y,
+            8 => self.char_width = if y == 0 { None } else { Some(8 * y as usize) },
+            _ => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "Operator descriptor {} not supported at this time.",
+                    desc.string_form()
+                ))));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/** How the data for the subsets is laid out in section 4. */
+#[derive(Clone, Copy, Debug)]
+enum Layout {
+    /** One subset at a time, each decoded with the full list of descriptors. */
+    Uncompressed,
+    /** All the subsets at once, each element is a reference value followed by the increments for
+     * this many subsets.
+     */
+    Compressed(usize),
+}
+
+impl Layout {
+    fn num_subsets(self) -> usize {
+        match self {
+            Layout::Uncompressed => 1,
+            Layout::Compressed(n) => n,
+        }
+    }
+}
+
+/** Read the raw (unscaled, without the reference value) values of an element for each subset. */
+fn read_raw_values(f: &mut BitBuffer, layout: Layout, bits: usize) -> Result<Vec<Option<u64>>, Box<dyn Error>> {
+    let num_subsets = match layout {
+        Layout::Uncompressed => return Ok(vec![f.read_u64(bits)?]),
+        Layout::Compressed(n) => n,
+    };
+
+    let min = f.read_u64(bits)?;
+    let inc_bits = f.read_u64(6)?
+        .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid compressed increment width"))?
+        as usize;
+
+    if inc_bits == 0 {
+        return Ok(vec![min; num_subsets]);
+    }
+
+    let min = min.ok_or(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Compressed increments with a missing reference value",
+    ))?;
+
+    // An increment with all bits set is a missing value.
+    (0..num_subsets)
+        .map(|_| Ok(f.read_u64(inc_bits)?.map(|inc| min + inc)))
+        .collect()
+}
+
+/** Read the text values of an element for each subset. */
+fn read_text_values(f: &mut BitBuffer, layout: Layout, bits: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    let num_subsets = match layout {
+        Layout::Uncompressed => return Ok(vec![f.read_text(bits)?]),
+        Layout::Compressed(n) => n,
+    };
+
+    let min = f.read_text(bits)?;
+    let inc_octets = f.read_u64(6)?
+        .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid compressed increment width"))?
+        as usize;
+
+    if inc_octets == 0 {
+        return Ok(vec![min; num_subsets]);
+    }
+
+    (0..num_subsets).map(|_| f.read_text(8 * inc_octets)).collect()
+}
 
 fn read_element_descriptor(
     f: &mut BitBuffer,
+    tables: &LocalTables,
+    ops: &Operators,
+    layout: Layout,
     desc: &Descriptor,
-) -> Result<Element, Box<dyn Error>> {
-    let desc = table_b::TABLE_B.get(&desc.string_form() as &str).ok_or(std::io::Error::other("Invalid Table B Entry"))?;
-    let bits = desc.width_bits;
-    let name = desc.element_name;
-
-    let value = match desc.units {
-        "CCITT IA5" => Value::Str(f.read_text(bits)?),
-
-        "Numeric" | "a" | "mon" | "d" | "h" | "min" | "s" => f
-            .read_i64(bits, desc.reference_val)?
-            .map(Value::Numeric)
-            .unwrap_or(Value::Missing),
+) -> Result<Vec<Element>, Box<dyn Error>> {
+    // An associated field (e.g. quality information) comes before every element except those in
+    // class 31, which describe the associated field itself. They aren't kept.
+    let associated_bits: usize = ops.associated_fields.iter().sum();
+    if associated_bits > 0 && desc.x_value() != 31 {
+        read_raw_values(f, layout, associated_bits)?;
+    }
 
-        "Code table" | "Flag table" => f.read_u64(bits)?.map(Value::Code).unwrap_or(Value::Missing),
+    let desc = tables.table_b(&desc.string_form()).ok_or(std::io::Error::other("Invalid Table B Entry"))?;
+    let name = desc.element_name;
 
-        _ => f
-            .read_f64(bits, desc.reference_val, desc.scale_val)?
-            .map(Value::Float)
-            .unwrap_or(Value::Missing),
+    // Operators don't apply to text, code tables, or flag tables.
+    let inc = ops.increase_scale_ref_width;
+    let bits = (desc.width_bits as i32 + ops.width_change + (10 * inc + 2) / 3) as usize;
+    let reference_val = desc.reference_val * 10i64.pow(inc as u32);
+    let scale_val = desc.scale_val + ops.scale_change + inc;
+
+    let to_i64 = |raw: u64| -> Result<i64, Box<dyn Error>> { Ok(i64::try_from(raw)? + reference_val) };
+
+    let values: Vec<Value> = match desc.units {
+        "CCITT IA5" => read_text_values(f, layout, ops.char_width.unwrap_or(desc.width_bits))?
+            .into_iter()
+            .map(Value::Str)
+            .collect(),
+
+        // Counts and times are integers unless they're scaled, e.g. fractional seconds with 202YYY.
+        "Numeric" | "a" | "mon" | "d" | "h" | "min" | "s" if scale_val == 0 => read_raw_values(f, layout, bits)?
+            .into_iter()
+            .map(|raw| Ok(raw.map(to_i64).transpose()?.map(Value::Numeric).unwrap_or(Value::Missing)))
+            .collect::<Result<_, Box<dyn Error>>>()?,
+
+        "Code table" | "Flag table" => read_raw_values(f, layout, desc.width_bits)?
+            .into_iter()
+            .map(|raw| raw.map(Value::Code).unwrap_or(Value::Missing))
+            .collect(),
+
+        _ => read_raw_values(f, layout, bits)?
+            .into_iter()
+            .map(|raw| {
+                let val = raw.map(to_i64).transpose()?.map(|v| v as f64).map(|v| {
+                    if scale_val != 0 { v / f64::powi(10.0, scale_val) } else { v }
+                });
+                Ok(val.map(Value::Float).unwrap_or(Value::Missing))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?,
     };
 
-    Ok(Element::new(value, desc.units, name, desc.fxy))
+    Ok(values
+        .into_iter()
+        .map(|value| Element::new(value, desc.units, name, desc.fxy))
+        .collect())
 }
 
-fn read_replication_descriptor<'a>(
+fn read_replication_descriptor(
     f: &mut BitBuffer,
+    tables: &LocalTables,
+    ops: &mut Operators,
+    layout: Layout,
     desc: &Descriptor,
-    iter: &mut dyn Iterator<Item = &'a Descriptor>,
-) -> Result<Replication, Box<dyn Error>> {
+    iter: &mut dyn Iterator<Item = &Descriptor>,
+) -> Result<Vec<Replication>, Box<dyn Error>> {
     debug_assert_eq!(desc.f_value(), 1, "Not a replication descriptor, f={}", desc.f_value());
 
     let num_descriptors = desc.x_value();
@@ -51,16 +200,28 @@ fn read_replication_descriptor<'a>(
         debug_assert_eq!(reps.f_value(), 0);
         debug_assert_eq!(reps.x_value(), 31);
         let bits = match reps.y_value() {
+            0 => 1,
             1 => 8,
             2 => 16,
-            _ => panic!(
-                "unimplemented replication descriptor: {}",
-                reps.string_form()
-            ),
+            _ => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "unimplemented replication descriptor: {}",
+                    reps.string_form()
+                ))));
+            }
         };
 
-        num_repititions = f.read_usize(bits)?
-            .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "Incomplete Replcation Descriptor"))?;
+        // Compressed subsets all have the same structure, so they must have the same count.
+        let counts = read_raw_values(f, layout, bits)?;
+        num_repititions = match counts.first() {
+            Some(&Some(count)) if counts.iter().all(|&c| c == Some(count)) => usize::try_from(count)?,
+            _ => {
+                return Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Incomplete Replcation Descriptor",
+                )));
+            }
+        };
     }
 
     let mut descriptors = Vec::with_capacity(num_descriptors as usize);
@@ -73,70 +234,138 @@ fn read_replication_descriptor<'a>(
     }
     let descriptors = descriptors;
 
-    let mut rep = Replication::new_with_capacity(num_repititions);
+    let mut items: Vec<Vec<Structure>> = (0..layout.num_subsets()).map(|_| vec![]).collect();
+    let mut block_len = 0;
 
     for _ in 0..num_repititions {
-        for desc in &descriptors {
-            let structure = match desc.f_value() {
-                0 => Structure::Element(read_element_descriptor(f, &desc)?),
-                1 => Structure::Replication(read_replication_descriptor(f, &desc, iter)?),
-                2 => panic!("Operator descriptors not supported at this time."),
-                3 => Structure::Group(read_sequence_descriptor(f, &desc)?),
-                _ => panic!("Unknown descriptor type."),
-            };
+        let mut block_iter = descriptors.iter().copied();
+        while let Some(desc) = block_iter.next() {
+            if let Some(structures) = read_descriptor(f, tables, ops, layout, desc, &mut block_iter)? {
+                for (subset, structure) in items.iter_mut().zip(structures) {
+                    subset.push(structure);
+                }
+            }
+        }
 
-            rep.push(structure);
+        if block_len == 0 {
+            block_len = match items.first() {
+                Some(first) => first.len(),
+                None => {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Replication in a message without any subsets",
+                    )));
+                }
+            };
         }
     }
 
-    Ok(rep)
+    Ok(items
+        .into_iter()
+        .map(|items| {
+            let mut rep = Replication::new_with_capacity(items.len(), block_len);
+            for structure in items {
+                rep.push(structure);
+            }
+            rep
+        })
+        .collect())
 }
 
-fn read_sequence_descriptor<'a>(
+fn read_sequence_descriptor(
     f: &mut BitBuffer,
+    tables: &LocalTables,
+    ops: &mut Operators,
+    layout: Layout,
     desc: &Descriptor,
-) -> Result<Group, Box<dyn Error>> {
-    let entry = table_d::TABLE_D.get(&desc.string_form() as &str).ok_or(std::io::Error::other("Invalid Table D Entry"))?;
+) -> Result<Vec<Group>, Box<dyn Error>> {
+    let entry = tables.table_d(&desc.string_form()).ok_or(std::io::Error::other("Invalid Table D Entry"))?;
     let sequence: Vec<Descriptor> = entry
         .elements
         .iter()
         .map(|d| Descriptor::from_string_form(d))
         .collect::<Result<_,_>>()?;
 
-    let mut group = Group::new_with_capacity(sequence.len(), entry.group_name, entry.fxy);
+    let mut groups: Vec<Group> = (0..layout.num_subsets())
+        .map(|_| Group::new_with_capacity(sequence.len(), entry.group_name, entry.fxy))
+        .collect();
     let mut desc_iter = sequence.iter();
 
-    loop {
-        let desc = match desc_iter.next() {
-            Some(desc) => desc,
-            None => break,
-        };
+    while let Some(desc) = desc_iter.next() {
+        if let Some(structures) = read_descriptor(f, tables, ops, layout, desc, &mut desc_iter)? {
+            for (group, structure) in groups.iter_mut().zip(structures) {
+                group.push(structure);
+            }
+        }
+    }
 
-        let structure = match desc.f_value() {
-            0 => Structure::Element(read_element_descriptor(f, &desc)?),
-            1 => Structure::Replication(read_replication_descriptor(f, &desc, &mut desc_iter)?),
-            2 => panic!("Operator descriptors not supported at this time."),
-            3 => Structure::Group(read_sequence_descriptor(f, &desc)?),
-            _ => panic!("Unknown descriptor type."),
-        };
+    Ok(groups)
+}
+
+/** Read the data for a single descriptor, one structure for each subset in the layout. Operators
+ * produce no data so they return `None`.
+ */
+fn read_descriptor(
+    f: &mut BitBuffer,
+    tables: &LocalTables,
+    ops: &mut Operators,
+    layout: Layout,
+    desc: &Descriptor,
+    iter: &mut dyn Iterator<Item = &Descriptor>,
+) -> Result<Option<Vec<Structure>>, Box<dyn Error>> {
+    let structures = match desc.f_value() {
+        0 => read_element_descriptor(f, tables, ops, layout, desc)?.into_iter().map(Structure::Element).collect(),
+        1 => read_replication_descriptor(f, tables, ops, layout, desc, iter)?
+            .into_iter()
+            .map(Structure::Replication)
+            .collect(),
+        2 => {
+            ops.apply(desc)?;
+            return Ok(None);
+        }
+        3 => read_sequence_descriptor(f, tables, ops, layout, desc)?.into_iter().map(Structure::Group).collect(),
+        _ => panic!("Unknown descriptor type."),
+    };
+
+    Ok(Some(structures))
+}
+
+/** Decode the descriptors once for every subset in the layout. */
+fn read_subsets(
+    f: &mut BitBuffer,
+    tables: &LocalTables,
+    layout: Layout,
+    descriptors: &[Descriptor],
+) -> Result<Vec<Vec<Structure>>, Box<dyn Error>> {
+    let mut desc_iter = descriptors.iter();
+    let mut ops = Operators::default();
+    let mut subsets: Vec<Vec<Structure>> = (0..layout.num_subsets()).map(|_| vec![]).collect();
 
-        group.push(structure);
+    while let Some(descriptor) = desc_iter.next() {
+        if let Some(structures) = read_descriptor(f, tables, &mut ops, layout, descriptor, &mut desc_iter)? {
+            for (subset, structure) in subsets.iter_mut().zip(structures) {
+                subset.push(structure);
+            }
+        }
     }
-    Ok(group)
+
+    Ok(subsets)
 }
 
+/** Decode the data in place, `f` is moved past the section. */
 pub(super) fn read_section_4(
-    mut f: impl Read,
+    f: &mut &[u8],
     descriptors: Vec<Descriptor>,
+    tables: &LocalTables,
     builder: &mut BufrMessageBuilder,
 ) -> Result<(), Box<dyn Error>> {
     let mut octets_read: usize = 0;
 
-    let section_size = read_3_octet_usize(&mut f)?
+    let section_size = read_3_octet_usize(&mut *f)?
         .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "Section Size Required"))?;
     octets_read += 3;
 
-    let _reserved: () = read_1_octet_u8(&mut f)?
+    let _reserved: () = read_1_octet_u8(&mut *f)?
         .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "Reserved Octet Required"))
         .and_then(|val| {
             match val {
@@ -148,42 +377,100 @@ pub(super) fn read_section_4(
 
     assert!(!descriptors.is_empty());
 
-    let mut desc_iter = descriptors.iter();
+    let bytes_left_in_section = section_size
+        .checked_sub(octets_read)
+        .filter(|&left| left <= f.len())
+        .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid Section Size"))?;
+    let (data, rest) = f.split_at(bytes_left_in_section);
+    let mut bit_buffer = BitBuffer::new(data);
+
+    let num_subsets = usize::from(builder.get_num_datasets());
+    let subsets = if builder.get_compressed_data() {
+        read_subsets(&mut bit_buffer, tables, Layout::Compressed(num_subsets), &descriptors)?
+    } else {
+        // Uncompressed subsets follow one another.
+        let mut subsets = Vec::with_capacity(num_subsets);
+        for _ in 0..num_subsets {
+            subsets.extend(read_subsets(&mut bit_buffer, tables, Layout::Uncompressed, &descriptors)?);
+        }
+        subsets
+    };
 
-    let bytes_left_in_section = section_size - octets_read;
-    let mut bit_buffer = BitBuffer::new(&mut f, bytes_left_in_section)?;
+    builder.subsets(subsets);
 
-    let mut vec = vec![];
+    *f = rest;
 
-    loop {
-        let descriptor = match desc_iter.next() {
-            Some(desc) => desc,
-            None => break,
-        };
+    Ok(())
+}
 
-        let structure = match descriptor.f_value() {
-            0 => Structure::Element(read_element_descriptor(&mut bit_buffer, descriptor)?),
-            1 => Structure::Replication(read_replication_descriptor(
-                &mut bit_buffer,
-                descriptor,
-                &mut desc_iter,
-            )?),
-            2 => panic!("Operator descriptors not supported at this time."),
-            3 => Structure::Group(read_sequence_descriptor(&mut bit_buffer, descriptor)?),
-            _ => panic!("Unknown descriptor type."),
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        vec.push(structure);
+    /** Pack a string of '0' and '1' into bytes, padded with zeros. Spaces are ignored. */
+    fn bits(s: &str) -> Vec<u8> {
+        let bits: Vec<u8> = s.bytes().filter(|&b| b != b' ').map(|b| b - b'0').collect();
+        bits.chunks(8)
+            .map(|chunk| chunk.iter().enumerate().fold(0, |byte, (i, &bit)| byte | bit << (7 - i)))
+            .collect()
     }
 
-    builder.elements(vec);
+    /** A delayed replication (031001) of temperature (012101). */
+    fn descriptors() -> Vec<Descriptor> {
+        ["101000", "031001", "012101"]
+            .iter()
+            .map(|d| Descriptor::from_string_form(d).unwrap())
+            .collect()
+    }
 
-    octets_read += bytes_left_in_section;
+    #[test]
+    fn test_compressed_replication() {
+        // Count 2 with no increments, then two temperatures with no increments.
+        let data = bits("00000010 000000 0111010101001100 000000 0110101110011000 000000");
+        let mut f = BitBuffer::new(&data);
+        let subsets = read_subsets(&mut f, &LocalTables::new(), Layout::Compressed(2), &descriptors()).unwrap();
+
+        assert_eq!(subsets.len(), 2);
+        for subset in &subsets {
+            match subset.as_slice() {
+                [Structure::Replication(rep)] => {
+                    let temps: Vec<Option<f64>> = rep
+                        .items()
+                        .iter()
+                        .map(|s| match s {
+                            Structure::Element(el) => el.get_f64_val(),
+                            _ => None,
+                        })
+                        .collect();
+                    assert_eq!(temps, vec![Some(300.28), Some(275.44)]);
+                }
+                _ => panic!("Expected a single replication, got {:?}", subset),
+            }
+        }
+    }
 
-    // Push the stream ahead to the end of the section
-    for _ in 0..(section_size - octets_read) {
-        let _v = read_1_octet_u8(&mut f)?;
+    #[test]
+    fn test_compressed_replication_counts_differ() {
+        // Reference 1 with 2 bit increments 0 and 1, so counts of 1 and 2.
+        let data = bits("00000001 000010 00 01 0111010101001100 000000 0110101110011000 000000");
+        let mut f = BitBuffer::new(&data);
+        assert!(read_subsets(&mut f, &LocalTables::new(), Layout::Compressed(2), &descriptors()).is_err());
+
+        // The count is missing in the second subset.
+        let data = bits("00000001 000010 00 11 0111010101001100 000000");
+        let mut f = BitBuffer::new(&data);
+        assert!(read_subsets(&mut f, &LocalTables::new(), Layout::Compressed(2), &descriptors()).is_err());
     }
 
-    Ok(())
+    #[test]
+    fn test_compressed_replication_without_subsets() {
+        let descriptors: Vec<Descriptor> = ["101002", "012101"]
+            .iter()
+            .map(|d| Descriptor::from_string_form(d).unwrap())
+            .collect();
+
+        let data = [0; 8];
+        let mut f = BitBuffer::new(&data);
+        assert!(read_subsets(&mut f, &LocalTables::new(), Layout::Compressed(0), &descriptors).is_err());
+    }
 }
@@ -1,5 +1,8 @@
 use std::{error::Error, io::Read};
 
+/** Length of section 5, "7777". */
+pub(super) const SECTION_5_SIZE: usize = 4;
+
 pub struct Section5 {}
 
 pub(super) fn read_section_5(mut f: impl Read) -> Result<Section5, Box<dyn Error>> {
@@ -1,6 +1,9 @@
+pub mod code_flag;
+pub mod local;
 pub mod table_b;
 pub mod table_d;
 
+#[derive(Clone, Debug)]
 pub struct TableBEntry {
     pub(crate) fxy: &'static str,
     pub(crate) width_bits: usize,
@@ -10,6 +13,7 @@ pub struct TableBEntry {
     pub(crate) scale_val: i32,
 }
 
+#[derive(Clone, Debug)]
 pub struct TableDEntry {
     pub(crate) fxy: &'static str,
     pub(crate) group_name: &'static str,
@@ -0,0 +1,385 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    sync::Mutex,
+};
+
+use super::{TableBEntry, TableDEntry, table_b, table_d};
+use crate::types::{BufrMessage, Structure};
+
+/** Data category (Table A) of messages that hold BUFR tables instead of data. */
+const TABLES_DATA_CATEGORY: u8 = 11;
+
+lazy_static! {
+    /** The local sequences NCEP uses to send its DX tables as data, one for each of Table A, B, and D. */
+    static ref DX_TABLE_D: HashMap<&'static str, TableDEntry> = [
+        ("360001", TableDEntry{fxy:"360001", group_name:r#"(DX Table A entries)"#, elements:vec![r#"103000"#, r#"031001"#, r#"000001"#, r#"000002"#, r#"000003"#]}),
+        ("360002", TableDEntry{fxy:"360002", group_name:r#"(DX Table B entries)"#, elements:vec![r#"101000"#, r#"031001"#, r#"300004"#]}),
+        ("360003", TableDEntry{fxy:"360003", group_name:r#"(DX Table D entries)"#, elements:vec![r#"105000"#, r#"031001"#, r#"300003"#, r#"000013"#, r#"101000"#, r#"031001"#, r#"000030"#]}),
+    ].into_iter().collect();
+
+    /** Strings from local tables. Decoded elements borrow their names and units from the tables
+     * for the life of the program, so the local ones are kept here and shared between tables
+     * loaded more than once.
+     */
+    static ref INTERNED: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+fn intern(s: &str) -> &'static str {
+    let mut interned = INTERNED.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(&s) = interned.get(s) {
+        return s;
+    }
+
+    let s: &'static str = Box::leak(s.to_owned().into_boxed_str());
+    interned.insert(s);
+    s
+}
+
+/** Table B and D entries defined by the data itself instead of the WMO tables.
+ *
+ * NCEP sends its own tables (DX tables) in the first messages of a file, ahead of the messages
+ * that use them. Add those messages to a `LocalTables` with `add_message`, then decode the rest of
+ * the file with `read_bufr_message_with_tables`. Local entries replace WMO entries with the same
+ * descriptor. NCEP names every entry with a mnemonic, like "TMDB" for temperature, which is the
+ * only reliable way to find a local element since the descriptors differ between tables.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct LocalTables {
+    table_b: HashMap<&'static str, TableBEntry>,
+    table_d: HashMap<&'static str, TableDEntry>,
+    descriptors: HashMap<&'static str, &'static str>,
+    mnemonics: HashMap<&'static str, &'static str>,
+}
+
+impl LocalTables {
+    /** Create an empty set of tables, which decodes messages with the WMO tables only. */
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /** Query if a message holds BUFR tables (data category 11) instead of data. */
+    pub fn is_tables_message(bufr: &BufrMessage) -> bool {
+        bufr.data_category() == TABLES_DATA_CATEGORY
+    }
+
+    /** Add the Table B and D entries from a DX tables message. Table A entries aren't needed to
+     * decode anything, so they're ignored.
+     */
+    pub fn add_message(&mut self, bufr: &BufrMessage) -> Result<(), Box<dyn Error>> {
+        if !Self::is_tables_message(bufr) {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Not a BUFR tables message.",
+            )));
+        }
+
+        for structure in bufr.subsets().iter().flatten() {
+            if let Structure::Group(grp) = structure {
+                for block in grp.items().iter().flat_map(replication_blocks) {
+                    match grp.code() {
+                        "360002" => self.add_table_b_entry(block)?,
+                        "360003" => self.add_table_d_entry(block)?,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /** Query if there are any local entries. */
+    pub fn is_empty(&self) -> bool {
+        self.table_b.is_empty() && self.table_d.is_empty()
+    }
+
+    /** Get the descriptor of a local entry from its mnemonic, e.g. "TMDB". */
+    pub fn descriptor(&self, mnemonic: &str) -> Option<&'static str> {
+        self.descriptors.get(mnemonic).copied()
+    }
+
+    /** Get the mnemonic of a local entry from its descriptor. */
+    pub fn mnemonic(&self, fxy: &str) -> Option<&'static str> {
+        self.mnemonics.get(fxy).copied()
+    }
+
+    pub(crate) fn table_b(&self, fxy: &str) -> Option<&TableBEntry> {
+        self.table_b.get(fxy).or_else(|| table_b::TABLE_B.get(fxy))
+    }
+
+    pub(crate) fn table_d(&self, fxy: &str) -> Option<&TableDEntry> {
+        self.table_d
+            .get(fxy)
+            .or_else(|| table_d::TABLE_D.get(fxy))
+            .or_else(|| DX_TABLE_D.get(fxy))
+    }
+
+    /** From the elements of a Table B element definition (300004). */
+    fn add_table_b_entry(&mut self, block: &[Structure]) -> Result<(), Box<dyn Error>> {
+        let fxy = descriptor(block)?;
+        let (mnemonic, element_name) = name(block)?;
+
+        let width_bits: usize = required_text(block, "000020")?.trim().parse()?;
+        if width_bits == 0 || width_bits > table_b::MAX_BIT_WIDTH {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid width for local descriptor {}: {}", fxy, width_bits),
+            )));
+        }
+
+        let entry = TableBEntry {
+            fxy,
+            width_bits,
+            element_name,
+            units: units(required_text(block, "000015")?),
+            reference_val: signed(block, "000018", "000019")?,
+            scale_val: i32::try_from(signed(block, "000016", "000017")?)?,
+        };
+
+        self.table_b.insert(fxy, entry);
+        self.add_mnemonic(mnemonic, fxy);
+
+        Ok(())
+    }
+
+    /** From the descriptor, name, and list of descriptors in a Table D sequence definition. */
+    fn add_table_d_entry(&mut self, block: &[Structure]) -> Result<(), Box<dyn Error>> {
+        let fxy = descriptor(block)?;
+        let (mnemonic, group_name) = name(block)?;
+
+        let mut elements = vec![];
+        for item in block.iter().flat_map(replication_blocks).flatten() {
+            if let Structure::Element(el) = item
+                && el.code() == "000030"
+            {
+                elements.push(valid_descriptor(el.get_str_val().unwrap_or_default().trim())?);
+            }
+        }
+
+        self.table_d.insert(fxy, TableDEntry { fxy, group_name, elements });
+        self.add_mnemonic(mnemonic, fxy);
+
+        Ok(())
+    }
+
+    fn add_mnemonic(&mut self, mnemonic: &'static str, fxy: &'static str) {
+        if !mnemonic.is_empty() {
+            self.descriptors.insert(mnemonic, fxy);
+            self.mnemonics.insert(fxy, mnemonic);
+        }
+    }
+}
+
+/** The repetitions of a replication, or nothing for any other structure. */
+fn replication_blocks(structure: &Structure) -> Box<dyn Iterator<Item = &[Structure]> + '_> {
+    match structure {
+        Structure::Replication(rep) => Box::new(rep.blocks()),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+/** Find the text of an element, including those in sequences, without trimming it. */
+fn text<'a>(items: &'a [Structure], code: &str) -> Option<&'a str> {
+    items.iter().find_map(|structure| match structure {
+        Structure::Element(el) if el.code() == code => el.get_str_val(),
+        Structure::Group(grp) => text(grp.items(), code),
+        _ => None,
+    })
+}
+
+fn required_text<'a>(items: &'a [Structure], code: &str) -> Result<&'a str, Box<dyn Error>> {
+    text(items, code).ok_or_else(|| {
+        Box::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Table entry missing {}", code),
+        ))
+    })
+}
+
+/** The descriptor being defined, from the F, X, and Y (000010, 000011, 000012) of 300003. */
+fn descriptor(items: &[Structure]) -> Result<&'static str, Box<dyn Error>> {
+    let fxy: String = ["000010", "000011", "000012"]
+        .iter()
+        .map(|code| required_text(items, code).map(str::trim))
+        .collect::<Result<_, _>>()?;
+
+    valid_descriptor(&fxy)
+}
+
+fn valid_descriptor(fxy: &str) -> Result<&'static str, Box<dyn Error>> {
+    if fxy.len() != 6 || !fxy.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid descriptor in table entry: {:?}", fxy),
+        )));
+    }
+
+    Ok(intern(fxy))
+}
+
+/** The mnemonic and full name from the element name (000013, 000014). NCEP puts the mnemonic in
+ * the first 8 characters and the description after it.
+ */
+fn name(items: &[Structure]) -> Result<(&'static str, &'static str), Box<dyn Error>> {
+    let mut line = required_text(items, "000013")?.to_owned();
+    line.push_str(text(items, "000014").unwrap_or_default());
+
+    let split = line.char_indices().nth(8).map(|(i, _)| i).unwrap_or(line.len());
+    let (mnemonic, description) = line.split_at(split);
+    let (mnemonic, description) = (mnemonic.trim(), description.trim());
+
+    let name = match (mnemonic.is_empty(), description.is_empty()) {
+        (_, true) => mnemonic.to_owned(),
+        (true, false) => description.to_owned(),
+        (false, false) => format!("{} {}", mnemonic, description),
+    };
+
+    Ok((intern(mnemonic), intern(&name)))
+}
+
+/** Use the spelling of the WMO tables for the units that change how an element is decoded. */
+fn units(units: &str) -> &'static str {
+    let units = units.trim();
+    match units.to_ascii_uppercase().as_str() {
+        "CCITT IA5" | "CCITT_IA5" => "CCITT IA5",
+        "CODE TABLE" => "Code table",
+        "FLAG TABLE" => "Flag table",
+        "NUMERIC" => "Numeric",
+        _ => intern(units),
+    }
+}
+
+/** A signed value stored as a sign character and the digits in separate elements. */
+fn signed(items: &[Structure], sign_code: &str, digits_code: &str) -> Result<i64, Box<dyn Error>> {
+    let value: i64 = required_text(items, digits_code)?.trim().parse()?;
+
+    match text(items, sign_code).map(str::trim) {
+        Some("-") => Ok(-value),
+        _ => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        read_bufr_message_from_slice, read_bufr_message_from_slice_with_tables,
+        test_util::{Bits, DxElement, DxSequence, dx_tables, message},
+    };
+
+    const ELEMENTS: [DxElement; 4] = [
+        ("063002", "STID    Station identifier", "CCITT_IA5", 0, 0, 64),
+        ("063004", "CLAT    Latitude", "DEGREES", 2, -9000, 15),
+        ("063010", "PRES    Pressure", "PASCALS", -1, 0, 14),
+        ("063011", "TMDB", "DEGREES KELVIN", 1, 0, 12),
+    ];
+
+    const SEQUENCES: [DxSequence; 2] = [
+        ("363000", "CLASS1  Model sounding", &["063002", "063004", "101000", "031001", "363002"]),
+        ("363002", "PROFILE", &["063010", "063011"]),
+    ];
+
+    fn tables() -> LocalTables {
+        let bufr = read_bufr_message_from_slice(&dx_tables(&ELEMENTS, &SEQUENCES)).unwrap();
+        assert!(LocalTables::is_tables_message(&bufr));
+
+        let mut tables = LocalTables::new();
+        tables.add_message(&bufr).unwrap();
+        tables
+    }
+
+    /** Every element in the items, including those in sequences and replications. */
+    fn values(items: &[Structure]) -> Vec<(&str, Option<f64>)> {
+        items
+            .iter()
+            .flat_map(|structure| match structure {
+                Structure::Element(el) => vec![(el.code(), el.get_f64_val())],
+                Structure::Group(grp) => values(grp.items()),
+                Structure::Replication(rep) => values(rep.items()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_add_message() {
+        let tables = tables();
+
+        assert_eq!(tables.descriptor("TMDB"), Some("063011"));
+        assert_eq!(tables.mnemonic("063004"), Some("CLAT"));
+        assert_eq!(tables.descriptor("CLASS1"), Some("363000"));
+        assert_eq!(tables.descriptor("Latitude"), None);
+
+        let clat = tables.table_b("063004").unwrap();
+        assert_eq!(clat.width_bits, 15);
+        assert_eq!(clat.scale_val, 2);
+        assert_eq!(clat.reference_val, -9000);
+        assert_eq!(clat.element_name, "CLAT Latitude");
+        assert_eq!(clat.units, "DEGREES");
+
+        assert_eq!(tables.table_b("063002").unwrap().units, "CCITT IA5");
+        assert_eq!(tables.table_b("063010").unwrap().scale_val, -1);
+        assert_eq!(tables.table_b("063011").unwrap().element_name, "TMDB");
+
+        let class1 = tables.table_d("363000").unwrap();
+        assert_eq!(class1.group_name, "CLASS1 Model sounding");
+        assert_eq!(class1.elements, SEQUENCES[0].2);
+
+        // The WMO tables are still there.
+        assert_eq!(tables.table_b("012101").unwrap().width_bits, 16);
+    }
+
+    #[test]
+    fn test_decode_with_local_tables() {
+        let tables = tables();
+
+        let mut data = Bits::with_tables(tables.clone());
+        data.text("063002", "KMSO")
+            .element("063004", Some(-45.5))
+            .element("031001", Some(2.0));
+        for (p, t) in [(85_000.0, Some(280.5)), (70_000.0, None)] {
+            data.element("063010", Some(p)).element("063011", t);
+        }
+
+        let bytes = message(&["363000"], &data, 1, 102, false);
+        let bufr = read_bufr_message_from_slice_with_tables(&bytes, &tables).unwrap();
+        let subset = &bufr.subsets()[0];
+
+        match &subset[0] {
+            Structure::Group(grp) => {
+                assert_eq!(grp.code(), "363000");
+                match &grp.items()[0] {
+                    Structure::Element(el) => assert_eq!(el.get_str_val().map(str::trim), Some("KMSO")),
+                    _ => panic!("Expected the station identifier"),
+                }
+            }
+            _ => panic!("Expected the local sequence"),
+        }
+
+        assert_eq!(
+            values(subset)[1..],
+            [
+                ("063004", Some(-45.5)),
+                ("063010", Some(85_000.0)),
+                ("063011", Some(280.5)),
+                ("063010", Some(70_000.0)),
+                ("063011", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_message_errors() {
+        let mut tables = LocalTables::new();
+
+        let bytes = message(&["012101"], Bits::new().element("012101", Some(280.0)), 1, 0, false);
+        let bufr = read_bufr_message_from_slice(&bytes).unwrap();
+        assert!(tables.add_message(&bufr).is_err());
+
+        let zero_width = [("063011", "TMDB", "DEGREES KELVIN", 1, 0, 0)];
+        let bufr = read_bufr_message_from_slice(&dx_tables(&zero_width, &[])).unwrap();
+        assert!(tables.add_message(&bufr).is_err());
+        assert!(tables.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+// Encode small BUFR edition 4 messages for tests, with the element widths, scales, and reference
+// values looked up in the tables.
+
+use crate::tables::local::LocalTables;
+
+/** Section 4 data, written one element at a time. */
+#[derive(Default)]
+pub(crate) struct Bits {
+    bits: Vec<bool>,
+    tables: LocalTables,
+}
+
+impl Bits {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /** Look up local descriptors in `tables` too. */
+    pub(crate) fn with_tables(tables: LocalTables) -> Self {
+        Bits { bits: vec![], tables }
+    }
+
+    /** Write the low `width` bits of `value`. */
+    pub(crate) fn put(&mut self, value: u64, width: usize) -> &mut Self {
+        self.bits.extend((0..width).rev().map(|i| value >> i & 1 == 1));
+        self
+    }
+
+    /** Write a numeric, code, or flag table element, `None` is written as missing. */
+    pub(crate) fn element(&mut self, fxy: &str, value: Option<f64>) -> &mut Self {
+        let (width, raw) = self.raw(fxy, value);
+        self.put(raw.unwrap_or(u64::MAX), width)
+    }
+
+    /** Write a CCITT IA5 element, padded with spaces. */
+    pub(crate) fn text(&mut self, fxy: &str, value: &str) -> &mut Self {
+        let width = self.entry(fxy).0;
+        for i in 0..(width / 8) {
+            self.put(u64::from(value.as_bytes().get(i).copied().unwrap_or(b' ')), 8);
+        }
+        self
+    }
+
+    /** Write an element for all the subsets of a compressed message. */
+    pub(crate) fn compressed(&mut self, fxy: &str, values: &[Option<f64>]) -> &mut Self {
+        let width = self.entry(fxy).0;
+        let raws: Vec<Option<u64>> = values.iter().map(|&v| self.raw(fxy, v).1).collect();
+
+        let present: Vec<u64> = raws.iter().flatten().copied().collect();
+        if raws.iter().all(|&raw| raw == raws[0]) {
+            return self.put(raws[0].unwrap_or(u64::MAX), width).put(0, 6);
+        }
+
+        let min = present.iter().copied().min().unwrap_or(0);
+        let span = present.iter().copied().max().unwrap_or(0) - min;
+        let inc_width = (1..64).find(|&n| (1 << n) - 1 > span).unwrap();
+
+        self.put(min, width).put(inc_width as u64, 6);
+        for raw in raws {
+            self.put(raw.map_or(u64::MAX, |raw| raw - min), inc_width);
+        }
+        self
+    }
+
+    /** Pack the bits into octets, padded with zeros to an even number of octets. */
+    pub(crate) fn bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self
+            .bits
+            .chunks(8)
+            .map(|chunk| chunk.iter().enumerate().fold(0, |byte, (i, &bit)| byte | (bit as u8) << (7 - i)))
+            .collect();
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn entry(&self, fxy: &str) -> (usize, i64, i32) {
+        let entry = self.tables.table_b(fxy).unwrap_or_else(|| panic!("No Table B entry for {}", fxy));
+        (entry.width_bits, entry.reference_val, entry.scale_val)
+    }
+
+    fn raw(&self, fxy: &str, value: Option<f64>) -> (usize, Option<u64>) {
+        let (width, reference, scale) = self.entry(fxy);
+        let raw = value.map(|v| ((v * 10f64.powi(scale)).round() as i64 - reference) as u64);
+        (width, raw)
+    }
+}
+
+/** Frame the data in a message with the descriptors in section 3 and the data category in
+ * section 1. The message is from 2020-01-01 12:00.
+ */
+pub(crate) fn message(descriptors: &[&str], data: &Bits, subsets: u16, data_category: u8, compressed: bool) -> Vec<u8> {
+    let mut section_1: Vec<u8> = vec![0, 0, 22, 0, 0, 7, 0, 0, 0, 0, data_category, 0, 0, 33, 0];
+    section_1.extend_from_slice(&2020u16.to_be_bytes());
+    section_1.extend_from_slice(&[1, 1, 12, 0, 0]);
+
+    let mut section_3: Vec<u8> = vec![0];
+    section_3.extend_from_slice(&subsets.to_be_bytes());
+    section_3.push(if compressed { 0xC0 } else { 0x80 });
+    for desc in descriptors {
+        let f: u16 = desc[0..1].parse().unwrap();
+        let x: u16 = desc[1..3].parse().unwrap();
+        let y: u16 = desc[3..6].parse().unwrap();
+        section_3.extend_from_slice(&(f << 14 | x << 8 | y).to_be_bytes());
+    }
+
+    let data = data.bytes();
+
+    let mut body = section_1;
+    body.extend_from_slice(&(section_3.len() as u32 + 3).to_be_bytes()[1..]);
+    body.extend_from_slice(&section_3);
+    body.extend_from_slice(&(data.len() as u32 + 4).to_be_bytes()[1..]);
+    body.push(0);
+    body.extend_from_slice(&data);
+    body.extend_from_slice(b"7777");
+
+    let mut message = b"BUFR".to_vec();
+    message.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes()[1..]);
+    message.push(4);
+    message.extend_from_slice(&body);
+    message
+}
+
+/** A Table B entry for `dx_tables`: the descriptor, the name with the mnemonic in the first 8
+ * characters, units, scale, reference value, and width.
+ */
+pub(crate) type DxElement<'a> = (&'a str, &'a str, &'a str, i32, i64, usize);
+
+/** A Table D entry for `dx_tables`: the descriptor, the name, and the descriptors in the sequence. */
+pub(crate) type DxSequence<'a> = (&'a str, &'a str, &'a [&'a str]);
+
+/** An NCEP DX tables message (data category 11) without any Table A entries. */
+pub(crate) fn dx_tables(elements: &[DxElement], sequences: &[DxSequence]) -> Vec<u8> {
+    let sign = |value: i64| if value < 0 { "-" } else { "+" };
+
+    let mut data = Bits::new();
+    data.element("031001", Some(0.0)).element("031001", Some(elements.len() as f64));
+    for &(fxy, name, units, scale, reference, width) in elements {
+        data.text("000010", &fxy[0..1])
+            .text("000011", &fxy[1..3])
+            .text("000012", &fxy[3..6])
+            .text("000013", name)
+            .text("000014", "")
+            .text("000015", units)
+            .text("000016", sign(i64::from(scale)))
+            .text("000017", &format!("{:03}", scale.abs()))
+            .text("000018", sign(reference))
+            .text("000019", &format!("{:010}", reference.abs()))
+            .text("000020", &format!("{:03}", width));
+    }
+
+    data.element("031001", Some(sequences.len() as f64));
+    for &(fxy, name, descriptors) in sequences {
+        data.text("000010", &fxy[0..1])
+            .text("000011", &fxy[1..3])
+            .text("000012", &fxy[3..6])
+            .text("000013", name)
+            .element("031001", Some(descriptors.len() as f64));
+        for desc in descriptors {
+            data.text("000030", desc);
+        }
+    }
+
+    message(&["360001", "360002", "360003"], &data, 1, 11, false)
+}
@@ -1,3 +1,4 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use std::fmt::Display;
 
 pub(crate) mod message_builder;
@@ -29,10 +30,11 @@ pub struct BufrMessage {
     num_datasets: u16,
     observed_data: bool,
     compressed_data: bool,
+    descriptors: Vec<String>,
 
     section_2_data: Vec<u8>,
 
-    elements: Vec<Structure>,
+    subsets: Vec<Vec<Structure>>,
 }
 
 impl BufrMessage {
@@ -54,9 +56,50 @@ impl BufrMessage {
         !self.extra_section_1_data.is_empty()
     }
 
-    /** Get the elements vector, or a vector of structures. */
+    /** Get the elements vector, or a vector of structures, of the first subset. */
     pub fn get_elements(&self) -> &[Structure] {
-        &self.elements
+        self.subsets.first().map(|s| s.as_slice()).unwrap_or(&[])
+    }
+
+    /** Get the structures of every subset (dataset) in the message. */
+    pub fn subsets(&self) -> &[Vec<Structure>] {
+        &self.subsets
+    }
+
+    /** Get the master table from section 1, 0 for meteorology or 10 for oceanography. */
+    pub fn master_table(&self) -> u8 {
+        self.master_table
+    }
+
+    /** Get the data category (BUFR Table A) from section 1. */
+    pub fn data_category(&self) -> u8 {
+        self.data_category
+    }
+
+    /** Get the international data subcategory (Common Code table C-13) from section 1. */
+    pub fn data_subcategory(&self) -> u8 {
+        self.data_subcategory
+    }
+
+    /** Get the local data subcategory from section 1, defined by the originating center. */
+    pub fn local_data_subcategory(&self) -> Option<u8> {
+        self.local_data_subcategory
+    }
+
+    /** Get the descriptors from section 3 before they are expanded, e.g. "309052". */
+    pub fn descriptors(&self) -> &[String] {
+        &self.descriptors
+    }
+
+    /** Get the typical time of the data from section 1. For model output this is usually the
+     * initialization time.
+     */
+    pub fn typical_time(&self) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(i32::from(self.year), u32::from(self.month), u32::from(self.day))?.and_hms_opt(
+            u32::from(self.hour),
+            u32::from(self.minute),
+            u32::from(self.second),
+        )
     }
 
     fn master_table_str(&self) -> &'static str {
@@ -108,8 +151,14 @@ impl Display for BufrMessage {
         writeln!(f, "-------------------- Data --------------------")?;
         writeln!(f)?;
 
-        for structure in self.elements.iter() {
-            structure::print_structure_data(f, structure, &mut vec![])?;
+        for (i, subset) in self.subsets.iter().enumerate() {
+            if self.subsets.len() > 1 {
+                writeln!(f, "------------------ Subset {} ------------------", i + 1)?;
+            }
+
+            for structure in subset.iter() {
+                structure::print_structure_data(f, structure, &mut vec![])?;
+            }
         }
 
         Ok(())
@@ -29,10 +29,11 @@ impl BufrMessageBuilder {
                 num_datasets: !0,
                 observed_data: false,
                 compressed_data: false,
+                descriptors: vec![],
 
                 section_2_data: vec![],
 
-                elements: vec![],
+                subsets: vec![],
             },
         }
     }
@@ -137,21 +138,32 @@ impl BufrMessageBuilder {
         self
     }
 
+    pub fn descriptors(&mut self, descriptors: Vec<String>) -> &mut Self {
+        self.bm.descriptors = descriptors;
+        self
+    }
+
     pub fn section_2_data(&mut self, section_2_data: Vec<u8>) -> &mut Self {
         self.bm.section_2_data = section_2_data;
         self
     }
 
-    pub fn elements(&mut self, elements: Vec<Structure>) -> &mut Self {
-        self.bm.elements = elements;
+    pub fn subsets(&mut self, subsets: Vec<Vec<Structure>>) -> &mut Self {
+        self.bm.subsets = subsets;
         self
     }
 
-    pub fn build(self) -> BufrMessage {
-        if self.bm.num_datasets > 1 {
-            panic!("multiple datasets not implemented");
-        }
+    /** The number of subsets set from section 3, needed to decode section 4. */
+    pub fn get_num_datasets(&self) -> u16 {
+        self.bm.num_datasets
+    }
+
+    /** Whether the data in section 4 is compressed, set from section 3. */
+    pub fn get_compressed_data(&self) -> bool {
+        self.bm.compressed_data
+    }
 
+    pub fn build(self) -> BufrMessage {
         if self.bm.bufr_master_table_version > crate::MAX_BUFR_TABLE_VERSION_SUPPORTED {
             panic!("data encoded with tables newer than supported in this version");
         }
@@ -49,6 +49,14 @@ impl Element {
         }
     }
 
+    pub fn get_code_val(&self) -> Option<u64> {
+        if let Value::Code(code) = self.val {
+            Some(code)
+        } else {
+            None
+        }
+    }
+
     pub fn get_f64_val(&self) -> Option<f64> {
         if let Value::Float(num) = self.val {
             Some(num)
@@ -98,12 +106,17 @@ impl Group {
 #[derive(Debug)]
 pub struct Replication {
     items: Vec<Structure>,
+    block_len: usize,
 }
 
 impl Replication {
-    pub fn new_with_capacity(cap: usize) -> Self {
+    /** Create a replication where each repetition of the replicated descriptors produces
+     * `block_len` structures.
+     */
+    pub fn new_with_capacity(cap: usize, block_len: usize) -> Self {
         Replication {
             items: Vec::with_capacity(cap),
+            block_len,
         }
     }
 
@@ -118,6 +131,11 @@ impl Replication {
     pub fn items(&self) -> &[Structure] {
         &self.items
     }
+
+    /** Iterate over the repetitions, each is a slice with the structures of one repetition. */
+    pub fn blocks(&self) -> impl Iterator<Item = &[Structure]> {
+        self.items.chunks(self.block_len.max(1))
+    }
 }
 
 #[derive(Debug)]